@@ -0,0 +1,14 @@
+-- Unconfirmed eligible transactions, removed once they are confirmed or evicted from the mempool.
+CREATE TABLE mempool_transactions (
+	id INTEGER PRIMARY KEY,
+	txid TEXT NOT NULL UNIQUE,
+	scalar TEXT NOT NULL
+);
+
+CREATE TABLE mempool_outputs (
+	id INTEGER PRIMARY KEY,
+	tx INTEGER NOT NULL REFERENCES mempool_transactions(id),
+	vout INTEGER NOT NULL,
+	value INTEGER NOT NULL,
+	script_pub_key TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Highest value of the transaction's taproot outputs, used to filter out transactions that only
+-- create dust.
+ALTER TABLE transactions ADD COLUMN max_value INTEGER NOT NULL DEFAULT 0;
+
+UPDATE transactions SET max_value = (SELECT COALESCE(MAX(value), 0) FROM outputs WHERE tx = transactions.id);
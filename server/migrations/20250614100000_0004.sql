@@ -0,0 +1,8 @@
+-- Spending transaction and its block height of spent outputs, both NULL while unspent.
+ALTER TABLE outputs ADD COLUMN spent_txid TEXT;
+ALTER TABLE outputs ADD COLUMN spent_height INTEGER;
+
+-- Spent outpoints are looked up by txid and un-spent by height on reorgs.
+CREATE INDEX transactions_txid ON transactions(txid);
+CREATE INDEX outputs_tx ON outputs(tx);
+CREATE INDEX outputs_spent_height ON outputs(spent_height);
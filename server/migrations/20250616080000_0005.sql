@@ -0,0 +1,7 @@
+-- Hex encoded compact block filters, one per block and kind.
+CREATE TABLE filters (
+	block INTEGER NOT NULL REFERENCES blocks(height),
+	kind TEXT NOT NULL,
+	data TEXT NOT NULL,
+	PRIMARY KEY (block, kind)
+);
@@ -0,0 +1,7 @@
+-- Index of the transaction in its block. Rows of existing transactions were inserted in block order,
+-- so their ids keep the order within each block.
+ALTER TABLE transactions ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE transactions SET position = id;
+
+CREATE INDEX transactions_block_position ON transactions(block, position);
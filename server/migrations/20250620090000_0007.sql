@@ -0,0 +1,9 @@
+-- Watch-only wallets whose outputs are streamed to subscribers. Only the scan secret key is stored,
+-- the spend key is public. Keys are hex encoded, labels a JSON array of label integers.
+CREATE TABLE wallets (
+    id TEXT PRIMARY KEY NOT NULL,
+    scan_key TEXT NOT NULL,
+    spend_key TEXT NOT NULL,
+    birthday INTEGER NOT NULL,
+    labels TEXT NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Blocks below `height` were indexed before spends and filters were recorded. The syncer re-fetches
+-- them from the node from the top down and lowers `height` as it goes, so spends are complete for
+-- outputs of blocks at or above `height`.
+CREATE TABLE backfill (
+	height INTEGER NOT NULL
+);
+
+INSERT INTO backfill (height)
+SELECT COALESCE(MAX(b.height) + 1, 0) FROM blocks b
+WHERE NOT EXISTS (SELECT 1 FROM filters f WHERE f.block = b.height);
@@ -0,0 +1,21 @@
+-- Scan progress of wallets, the height and hash of the last scanned block. NULL until the first
+-- block is scanned, blocks are scanned from the wallet's birthday on.
+ALTER TABLE wallets ADD COLUMN scanned_height INTEGER;
+ALTER TABLE wallets ADD COLUMN scanned_hash TEXT;
+
+-- Outputs owned by wallets, found by scanning blocks. They are removed with their block on a chain
+-- reorganization. Tweaks and keys are hex encoded.
+CREATE TABLE wallet_outputs (
+    wallet TEXT NOT NULL,
+    block INTEGER NOT NULL,
+    block_hash TEXT NOT NULL,
+    txid TEXT NOT NULL,
+    vout INTEGER NOT NULL,
+    value INTEGER NOT NULL,
+    pub_key TEXT NOT NULL,
+    tweak TEXT NOT NULL,
+    label INTEGER,
+    PRIMARY KEY (wallet, txid, vout)
+);
+
+CREATE INDEX wallet_outputs_block ON wallet_outputs (block);
@@ -0,0 +1,247 @@
+// BIP-352 silent payment addresses: bech32m encoded scan and spend public keys, e.g. `sp1q...`.
+//
+// The first data character is the address version. Version 0 addresses hold exactly the 33 byte
+// scan key followed by the 33 byte spend key. Versions 1 to 30 are read the same way with any data
+// after the two keys ignored, so receivers of future addresses can still be paid. Version 31 is
+// reserved for backwards incompatible changes and rejected.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::bech32::{
+    Bech32m, Fe32, Hrp,
+    primitives::{
+        decode::CheckedHrpstring,
+        iter::{ByteIterExt, Fe32IterExt},
+    },
+};
+use secp256k1::PublicKey;
+
+use crate::{Error, Result};
+
+const KEYS_LENGTH: usize = 66;
+const INCOMPATIBLE_VERSION: u8 = 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    // Testnet and signet share the `tsp` prefix.
+    Testnet,
+    Regtest,
+}
+
+impl Network {
+    fn hrp(&self) -> Hrp {
+        let hrp = match self {
+            Network::Mainnet => "sp",
+            Network::Testnet => "tsp",
+            Network::Regtest => "sprt",
+        };
+        Hrp::parse_unchecked(hrp)
+    }
+
+    fn from_hrp(hrp: &Hrp) -> Option<Self> {
+        match hrp.as_str() {
+            "sp" => Some(Network::Mainnet),
+            "tsp" => Some(Network::Testnet),
+            "sprt" => Some(Network::Regtest),
+            _ => None,
+        }
+    }
+}
+
+impl From<bitcoin::Network> for Network {
+    fn from(network: bitcoin::Network) -> Self {
+        match network {
+            bitcoin::Network::Bitcoin => Network::Mainnet,
+            bitcoin::Network::Regtest => Network::Regtest,
+            _ => Network::Testnet,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentAddress {
+    version: u8,
+    network: Network,
+    scan_key: PublicKey,
+    spend_key: PublicKey,
+}
+
+impl SilentPaymentAddress {
+    // Version 0 address, the only version this server creates.
+    pub fn new(scan_key: PublicKey, spend_key: PublicKey, network: Network) -> Self {
+        Self {
+            version: 0,
+            network,
+            scan_key,
+            spend_key,
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn scan_key(&self) -> PublicKey {
+        self.scan_key
+    }
+
+    pub fn spend_key(&self) -> PublicKey {
+        self.spend_key
+    }
+}
+
+fn invalid(reason: impl fmt::Display) -> Error {
+    Error::Address(reason.to_string())
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Addresses are not bound by the 90 character limit of BIP-173, bech32m allows 1023.
+        let checked = CheckedHrpstring::new::<Bech32m>(s).map_err(invalid)?;
+        let network = Network::from_hrp(&checked.hrp())
+            .ok_or_else(|| invalid(format!("unknown prefix {}", checked.hrp())))?;
+        // Characters of the data part are valid bech32 characters after the checksum check.
+        let mut fes = checked
+            .data_part_ascii_no_checksum()
+            .iter()
+            .map(|c| Fe32::from_char(char::from(*c)).expect("valid bech32 character"));
+        let version = fes
+            .next()
+            .ok_or_else(|| invalid("missing version"))?
+            .to_u8();
+        if version == INCOMPATIBLE_VERSION {
+            return Err(invalid(format!("unsupported version {version}")));
+        }
+        let data: Vec<u8> = fes.fes_to_bytes().collect();
+        let keys = match version {
+            0 if data.len() != KEYS_LENGTH => {
+                return Err(invalid(format!("invalid data length {}", data.len())));
+            }
+            _ => data
+                .get(..KEYS_LENGTH)
+                .ok_or_else(|| invalid(format!("invalid data length {}", data.len())))?,
+        };
+        let scan_key = PublicKey::from_slice(&keys[..33]).map_err(invalid)?;
+        let spend_key = PublicKey::from_slice(&keys[33..]).map_err(invalid)?;
+
+        Ok(Self {
+            version,
+            network,
+            scan_key,
+            spend_key,
+        })
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Data after the keys of future versions is not kept.
+        let version = Fe32::try_from(self.version).expect("version is below 32");
+        let keys = [self.scan_key.serialize(), self.spend_key.serialize()].concat();
+        let hrp = self.network.hrp();
+        let chars = keys
+            .into_iter()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&hrp)
+            .with_witness_version(version)
+            .chars();
+        for c in chars {
+            fmt::Write::write_char(f, c)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+    use crate::tests::bip352::receiving_cases;
+
+    fn public_key(secret_key_hex: &str) -> PublicKey {
+        let secret_key = SecretKey::from_str(secret_key_hex).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret_key)
+    }
+
+    #[test]
+    fn test_address_vectors() {
+        for case in receiving_cases() {
+            for expected in case.expected["addresses"].as_array().unwrap() {
+                let expected = expected.as_str().unwrap();
+                let address = SilentPaymentAddress::from_str(expected).unwrap();
+                assert_eq!(address.version(), 0, "{}", case.comment);
+                assert_eq!(address.network(), Network::Mainnet, "{}", case.comment);
+                assert_eq!(address.to_string(), expected, "{}", case.comment);
+            }
+
+            // The first address is the one without label.
+            let key_material = &case.given["key_material"];
+            let address = SilentPaymentAddress::new(
+                public_key(key_material["scan_priv_key"].as_str().unwrap()),
+                public_key(key_material["spend_priv_key"].as_str().unwrap()),
+                Network::Mainnet,
+            );
+            assert_eq!(
+                address.to_string(),
+                case.expected["addresses"][0].as_str().unwrap(),
+                "{}",
+                case.comment
+            );
+        }
+    }
+
+    fn encode(hrp: &str, version: u8, data: &[u8]) -> String {
+        data.iter()
+            .copied()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&Hrp::parse(hrp).unwrap())
+            .with_witness_version(Fe32::try_from(version).unwrap())
+            .chars()
+            .collect()
+    }
+
+    #[test]
+    fn test_address_versions() {
+        let scan_key = public_key(&"01".repeat(32));
+        let spend_key = public_key(&"02".repeat(32));
+        let keys = [scan_key.serialize(), spend_key.serialize()].concat();
+
+        let address = SilentPaymentAddress::from_str(&encode("tsp", 0, &keys)).unwrap();
+        assert_eq!(address.network(), Network::Testnet);
+        assert_eq!(address.scan_key(), scan_key);
+        assert_eq!(address.spend_key(), spend_key);
+
+        // Future versions may append data.
+        let extended = [keys.as_slice(), &[0xab; 10]].concat();
+        let address = SilentPaymentAddress::from_str(&encode("sprt", 1, &extended)).unwrap();
+        assert_eq!(address.version(), 1);
+        assert_eq!(address.network(), Network::Regtest);
+        assert_eq!(address.spend_key(), spend_key);
+        assert_eq!(address.to_string(), encode("sprt", 1, &keys));
+
+        // Version 0 has exactly two keys, version 31 is incompatible.
+        assert!(SilentPaymentAddress::from_str(&encode("sp", 0, &extended)).is_err());
+        assert!(SilentPaymentAddress::from_str(&encode("sp", 0, &keys[..65])).is_err());
+        assert!(SilentPaymentAddress::from_str(&encode("sp", 31, &keys)).is_err());
+        assert!(SilentPaymentAddress::from_str(&encode("bc", 0, &keys)).is_err());
+        // Bech32 instead of bech32m checksum.
+        let bech32: String = keys
+            .iter()
+            .copied()
+            .bytes_to_fes()
+            .with_checksum::<bitcoin::bech32::Bech32>(&Hrp::parse("sp").unwrap())
+            .with_witness_version(Fe32::Q)
+            .chars()
+            .collect();
+        assert!(SilentPaymentAddress::from_str(&bech32).is_err());
+    }
+}
@@ -11,18 +11,33 @@ pub struct Config {
 pub struct ServerConfig {
     pub server_host: String,
     pub server_port: String,
+    // Lowest birthday accepted for wallet registrations, scanning the blocks below it is refused.
+    pub wallet_min_birthday: i64,
+    // Maximum number of registered wallets, every new block is scanned for each of them.
+    pub max_wallets: i64,
+    // Maximum number of concurrent wallet subscriptions.
+    pub max_wallet_subscriptions: usize,
 }
 
 pub struct DatabaseConfig {
     pub database_url: String,
 }
 
+#[derive(Clone)]
 pub struct SyncerConfig {
     pub rpc_url: String,
     pub rpc_user: String,
     pub rpc_pass: String,
     pub sync_from: i64,
     pub cache_size: usize,
+    // Optional blocks directory of the node (e.g. `~/.bitcoin/blocks`) to catch up from before
+    // syncing with RPC.
+    pub blocks_dir: Option<String>,
+    // Number of blocks fetched and processed concurrently while the syncer is behind the tip.
+    pub parallelism: usize,
+    // Optional `zmqpubhashblock` endpoint of the node (e.g. `tcp://127.0.0.1:28332`) to index new
+    // blocks as soon as they arrive instead of polling for them.
+    pub zmq_block_url: Option<String>,
 }
 
 impl Config {
@@ -33,6 +48,15 @@ impl Config {
             server: ServerConfig {
                 server_host: get_env("SERVER_HOST")?,
                 server_port: get_env("SERVER_PORT")?,
+                wallet_min_birthday: get_env_opt("WALLET_MIN_BIRTHDAY")
+                    .map_or(Ok(0), |height| height.parse::<i64>())
+                    .map_err(|_| Error::Config)?,
+                max_wallets: get_env_opt("MAX_WALLETS")
+                    .map_or(Ok(1000), |count| count.parse::<i64>())
+                    .map_err(|_| Error::Config)?,
+                max_wallet_subscriptions: get_env_opt("MAX_WALLET_SUBSCRIPTIONS")
+                    .map_or(Ok(100), |count| count.parse::<usize>())
+                    .map_err(|_| Error::Config)?,
             },
             database: DatabaseConfig {
                 database_url: get_env("DATABASE_URL")?,
@@ -47,6 +71,12 @@ impl Config {
                 cache_size: get_env("CACHE_SIZE")?
                     .parse::<usize>()
                     .map_err(|_| Error::Config)?,
+                blocks_dir: get_env_opt("BLOCKS_DIR"),
+                // Sequential by default, as before parallel syncing was added.
+                parallelism: get_env_opt("SYNC_PARALLELISM")
+                    .map_or(Ok(1), |parallelism| parallelism.parse::<usize>())
+                    .map_err(|_| Error::Config)?,
+                zmq_block_url: get_env_opt("ZMQ_BLOCK_URL"),
             },
         })
     }
@@ -55,3 +85,7 @@ impl Config {
 fn get_env(key: &str) -> Result<String> {
     env::var(key).map_err(|_| Error::Config)
 }
+
+fn get_env_opt(key: &str) -> Option<String> {
+    env::var(key).ok()
+}
@@ -5,15 +5,42 @@ pub enum Error {
     Config,
     InvalidInput,
 
+    // -- module: lib.rs
+    #[from]
+    Tweak(TweakError),
+
+    // -- module: address.rs
+    // Malformed silent payment address.
+    Address(String),
+
+    // -- module: keys.rs
+    #[from]
+    Mnemonic(bip39::Error),
+    #[from]
+    Bip32(bitcoin::bip32::Error),
+
     // -- module server.rs
     // FIXME: Should belong to DB but right now handlers decide whether it was found or not..
     NotFound,
+    // Malformed request parameters.
+    BadRequest(String),
+    // The index did not reach the node's tip yet.
+    Syncing,
+    // Too many registered wallets or wallet subscriptions.
+    LimitReached(String),
+    // Malformed binary wire format data.
+    Decode(String),
 
     // -- module: sync.rs
     #[from]
     BitcoinRpc(bitcoincore_rpc::Error),
     #[from]
     SendBlock(tokio::sync::mpsc::error::SendError<crate::SPBlock>),
+    BlockFile(String),
+    #[from]
+    Join(tokio::task::JoinError),
+    #[from]
+    Zmq(zeromq::ZmqError),
 
     // -- module: store.rs
     #[from]
@@ -24,6 +51,22 @@ pub enum Error {
     // -- external
     #[from]
     Io(std::io::Error),
+    #[from]
+    Hex(hex::FromHexError),
+}
+
+// Reasons why no tweak can be computed for a transaction that is otherwise eligible, for one of its
+// outputs or for a label. BIP-352 receivers skip such transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TweakError {
+    // The input public keys sum up to the point at infinity.
+    InputKeySumIsInfinity,
+    // The input hash is not a valid scalar.
+    InvalidInputHash,
+    // The hash of the shared secret is not a valid scalar.
+    InvalidSharedSecretHash,
+    // The hash of a label is not a valid scalar or cancels out the spend key.
+    InvalidLabelHash,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
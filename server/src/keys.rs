@@ -0,0 +1,191 @@
+// Derivation of silent payment keys from a BIP-39 mnemonic as specified by BIP-352:
+//
+//   scan key   m/352'/coin_type'/account'/1'/0
+//   spend key  m/352'/coin_type'/account'/0'/0
+//
+// with coin type 0 for mainnet and 1 for testnets and regtest.
+
+use std::str::FromStr;
+
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv};
+use secp256k1::{Keypair, Secp256k1, SecretKey};
+
+use crate::Result;
+use crate::address::{Network, SilentPaymentAddress};
+use crate::scan::labeled_spend_key;
+
+const PURPOSE: u32 = 352;
+const SCAN_BRANCH: u32 = 1;
+const SPEND_BRANCH: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentKeys {
+    network: Network,
+    scan: Keypair,
+    spend: Keypair,
+}
+
+impl SilentPaymentKeys {
+    pub fn from_mnemonic(
+        mnemonic: &str,
+        passphrase: &str,
+        network: Network,
+        account: u32,
+    ) -> Result<Self> {
+        let mnemonic = bip39::Mnemonic::from_str(mnemonic)?;
+        Self::from_seed(&mnemonic.to_seed(passphrase), network, account)
+    }
+
+    pub fn from_seed(seed: &[u8], network: Network, account: u32) -> Result<Self> {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        // The network of the extended key only matters for its serialization.
+        let master = Xpriv::new_master(bitcoin::Network::Bitcoin, seed)?;
+        let coin_type = match network {
+            Network::Mainnet => 0,
+            Network::Testnet | Network::Regtest => 1,
+        };
+        let account_path = DerivationPath::from(vec![
+            ChildNumber::from_hardened_idx(PURPOSE)?,
+            ChildNumber::from_hardened_idx(coin_type)?,
+            ChildNumber::from_hardened_idx(account)?,
+        ]);
+        let account_key = master.derive_priv(&secp, &account_path)?;
+
+        // bip32 uses the secp256k1 version of the bitcoin crate, keys are passed on as bytes.
+        let derive = |branch: u32| -> Result<Keypair> {
+            let path = [
+                ChildNumber::from_hardened_idx(branch)?,
+                ChildNumber::from_normal_idx(0)?,
+            ];
+            let key = account_key.derive_priv(&secp, &path)?;
+            let secret_key = SecretKey::from_byte_array(&key.private_key.secret_bytes())
+                .expect("derived keys are valid secret keys");
+            Ok(Keypair::from_secret_key(&Secp256k1::new(), &secret_key))
+        };
+
+        Ok(Self {
+            network,
+            scan: derive(SCAN_BRANCH)?,
+            spend: derive(SPEND_BRANCH)?,
+        })
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn scan_key(&self) -> &Keypair {
+        &self.scan
+    }
+
+    pub fn spend_key(&self) -> &Keypair {
+        &self.spend
+    }
+
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress::new(
+            self.scan.public_key(),
+            self.spend.public_key(),
+            self.network,
+        )
+    }
+
+    // Address with label m, m = 0 is reserved for change.
+    pub fn labeled_address(&self, m: u32) -> Result<SilentPaymentAddress> {
+        let spend_key = labeled_spend_key(
+            &Secp256k1::verification_only(),
+            &self.scan.secret_key(),
+            &self.spend.public_key(),
+            m,
+        )?;
+        Ok(SilentPaymentAddress::new(
+            self.scan.public_key(),
+            spend_key,
+            self.network,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn test_derivation_paths() {
+        // BIP-39 test vector seed of the mnemonic with passphrase "TREZOR".
+        let seed = bip39::Mnemonic::from_str(MNEMONIC)
+            .unwrap()
+            .to_seed("TREZOR");
+        assert_eq!(
+            hex::encode(seed),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+
+        // Keys at m/352'/0'/0'/1'/0 and m/352'/0'/0'/0'/0 of this seed, computed with an independent
+        // BIP-32 implementation.
+        let keys =
+            SilentPaymentKeys::from_mnemonic(MNEMONIC, "TREZOR", Network::Mainnet, 0).unwrap();
+        assert_eq!(
+            hex::encode(keys.scan_key().secret_bytes()),
+            "2c74acb23861627693f58ebde7bf6c7b9da6f124e4a55055889916fd8ab21b5e"
+        );
+        assert_eq!(
+            keys.scan_key().public_key().to_string(),
+            "0293fbbcfbc490162b5eed583f0071ea7108512a1dbe0e917288f145c2b4ef74a0"
+        );
+        assert_eq!(
+            hex::encode(keys.spend_key().secret_bytes()),
+            "4eaac61eac3def90b7943f780c19cbd8a01236ad7e090ecb5673837afe01f23c"
+        );
+        assert_eq!(
+            keys.spend_key().public_key().to_string(),
+            "025a0314ea6974f81996d2d3c355801d3b5bcecaa7e9ad09e50d96445d17106a94"
+        );
+    }
+
+    #[test]
+    fn test_networks_and_accounts() {
+        let mainnet = SilentPaymentKeys::from_mnemonic(MNEMONIC, "", Network::Mainnet, 0).unwrap();
+        let testnet = SilentPaymentKeys::from_mnemonic(MNEMONIC, "", Network::Testnet, 0).unwrap();
+        let regtest = SilentPaymentKeys::from_mnemonic(MNEMONIC, "", Network::Regtest, 0).unwrap();
+        let account = SilentPaymentKeys::from_mnemonic(MNEMONIC, "", Network::Mainnet, 1).unwrap();
+
+        assert_eq!(
+            mainnet.address().to_string(),
+            "sp1qqfqnnv8czppwysafq3uwgwvsc638hc8rx3hscuddh0xa2yd746s7xqh6yy9ncjnqhqxazct0fzh98w7lpkm5fvlepqec2yy0sxlq4j6ccc3h6t0g"
+        );
+        assert!(mainnet.address().to_string().starts_with("sp1q"));
+        assert!(testnet.address().to_string().starts_with("tsp1q"));
+        assert!(regtest.address().to_string().starts_with("sprt1q"));
+
+        // Testnets and regtest share the coin type.
+        assert_ne!(mainnet.scan_key(), testnet.scan_key());
+        assert_eq!(testnet.scan_key(), regtest.scan_key());
+        assert_eq!(testnet.spend_key(), regtest.spend_key());
+        assert_ne!(mainnet.spend_key(), account.spend_key());
+        assert_ne!(mainnet.scan_key(), mainnet.spend_key());
+
+        let labeled = regtest.labeled_address(1).unwrap();
+        assert_eq!(labeled.scan_key(), regtest.scan_key().public_key());
+        assert_ne!(labeled.spend_key(), regtest.spend_key().public_key());
+        assert_ne!(labeled, regtest.labeled_address(2).unwrap());
+
+        let address = SilentPaymentAddress::from_str(&regtest.address().to_string()).unwrap();
+        assert_eq!(address.scan_key(), regtest.scan_key().public_key());
+        assert_eq!(address.spend_key(), regtest.spend_key().public_key());
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let invalid_checksum = MNEMONIC.replace("about", "abandon");
+        assert!(
+            SilentPaymentKeys::from_mnemonic(&invalid_checksum, "", Network::Mainnet, 0).is_err()
+        );
+        assert!(
+            SilentPaymentKeys::from_mnemonic("not a mnemonic", "", Network::Mainnet, 0).is_err()
+        );
+        assert!(SilentPaymentKeys::from_mnemonic(MNEMONIC, "", Network::Mainnet, 1 << 31).is_err());
+    }
+}
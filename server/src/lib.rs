@@ -1,13 +1,18 @@
 use bitcoin::hashes::Hash;
 use bitcoincore_rpc::bitcoin::{
-    OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Witness, WitnessVersion,
+    OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Witness, WitnessVersion, taproot::ControlBlock,
 };
-use secp256k1::{Parity, PublicKey, XOnlyPublicKey};
+use secp256k1::{Parity, PublicKey, Scalar, XOnlyPublicKey};
+use tracing::debug;
 
+pub mod address;
 pub mod config;
+pub mod keys;
+pub mod scan;
 pub mod server;
 pub mod store;
 pub mod sync;
+pub mod wallets;
 
 #[cfg(test)]
 pub mod tests;
@@ -15,7 +20,10 @@ pub mod tests;
 mod error;
 
 pub use self::config::Config;
-pub use self::error::{Error, Result};
+pub use self::error::{Error, Result, TweakError};
+
+// OP_1 0x20, prefix of taproot scriptPubKeys.
+pub(crate) const P2TR_PREFIX: [u8; 2] = [0x51, 0x20];
 
 #[derive(Debug, Clone)]
 pub struct SPBlock {
@@ -46,32 +54,77 @@ fn serialize_outpoint(outpoint: &OutPoint) -> Vec<u8> {
 }
 
 // hash = sha256(sha256(tag) || sha256(tag) || msg)
-fn hash_tag_inputs(msg: &[u8]) -> [u8; 32] {
-    let tag = b"BIP0352/Inputs";
+fn tagged_hash(tag: &[u8], msg: &[u8]) -> [u8; 32] {
     let tag_hash = bitcoin::hashes::sha256::Hash::hash(tag);
     let tag_tag_msg = [tag_hash.as_ref(), tag_hash.as_ref(), msg].concat();
-    let hash = bitcoin::hashes::sha256::Hash::hash(&tag_tag_msg).to_byte_array();
-    hash
+    bitcoin::hashes::sha256::Hash::hash(&tag_tag_msg).to_byte_array()
+}
+
+fn hash_tag_inputs(msg: &[u8]) -> [u8; 32] {
+    tagged_hash(b"BIP0352/Inputs", msg)
 }
 fn calculate_input_hash(outpoint: OutPoint, public_key_sum: PublicKey) -> [u8; 32] {
     let outpoint_ser = serialize_outpoint(&outpoint);
     let public_key_ser = public_key_sum.serialize();
     let msg = [outpoint_ser.as_slice(), &public_key_ser].concat();
-    let input_hash = hash_tag_inputs(msg.as_slice());
-    input_hash
+    hash_tag_inputs(msg.as_slice())
+}
+
+// Compute the public tweak data `input_hash * A` of a transaction from its inputs and the outputs
+// they spend (`prevouts[i]` is spent by `inputs[i]`). Returns `None` if the transaction is not
+// eligible because it spends an output with SegWit version > 1 or has no inputs for shared secret
+// derivation, and `Error::Tweak` if the transaction is eligible but has to be skipped anyway.
+pub fn compute_tweak(inputs: &[TxIn], prevouts: &[TxOut]) -> Result<Option<PublicKey>> {
+    // The transaction does not spend an output with SegWit version > 1
+    if has_output_witness_version_greater_v1(prevouts) {
+        debug!("Transaction spends an output with SegWit version > 1. Skipping.");
+        return Ok(None);
+    }
+
+    // The transaction has at least one input from the Inputs For Shared Secret Derivation list.
+    let public_keys: Vec<PublicKey> = inputs
+        .iter()
+        .zip(prevouts)
+        .flat_map(|(input, prevout)| try_get_input_public_key(input, prevout))
+        .collect();
+
+    if public_keys.is_empty() {
+        debug!("Transaction does not have any inputs for shared secret derivation");
+        return Ok(None);
+    }
+
+    // Keys are summed at once, only the final sum must not be the point at infinity.
+    let public_key_refs: Vec<&PublicKey> = public_keys.iter().collect();
+    let input_public_key_sum =
+        PublicKey::combine_keys(&public_key_refs).map_err(|_| TweakError::InputKeySumIsInfinity)?;
+
+    // Outpoints are compared byte-lexicographically in their serialized form, which is not the
+    // same as comparing by txid and then by vout as integer.
+    let lowest_outpoint = inputs
+        .iter()
+        .map(|txin| txin.previous_output)
+        .min_by_key(serialize_outpoint)
+        .expect("Transaction has at least one input");
+
+    let input_hash = calculate_input_hash(lowest_outpoint, input_public_key_sum);
+    let input_hash = Scalar::from_be_bytes(input_hash).map_err(|_| TweakError::InvalidInputHash)?;
+    let secp = secp256k1::Secp256k1::verification_only();
+    let tweak = input_public_key_sum
+        .mul_tweak(&secp, &input_hash)
+        .map_err(|_| TweakError::InvalidInputHash)?;
+
+    Ok(Some(tweak))
 }
 
 pub fn has_taproot_outputs(tx: &Transaction) -> bool {
     tx.output.iter().any(|txout| txout.script_pubkey.is_p2tr())
 }
 
-pub fn has_output_witness_version_greater_v1(outputs: &Vec<TxOut>) -> bool {
-    outputs
-        .iter()
-        .any(|txout| output_witness_version_greater_v1(txout))
+pub fn has_output_witness_version_greater_v1(outputs: &[TxOut]) -> bool {
+    outputs.iter().any(output_witness_version_greater_v1)
 }
 
-pub fn has_input_for_shared_secret(inputs: &Vec<TxIn>, prevouts: &Vec<TxOut>) -> bool {
+pub fn has_input_for_shared_secret(inputs: &[TxIn], prevouts: &[TxOut]) -> bool {
     inputs
         .iter()
         .zip(prevouts)
@@ -117,42 +170,43 @@ pub fn try_get_input_public_key(input: &TxIn, prevout: &TxOut) -> Result<PublicK
     Err(Error::InvalidInput)
 }
 
+// Get the public key of a P2PKH input as defined in BIP-352. The scriptSig is scanned from the end
+// for 33 bytes hashing to the public key hash of the scriptPubKey, so data pushed in front of the
+// public key by a malleated scriptSig is never picked up. Only compressed keys can match, inputs
+// spending to an uncompressed key are skipped.
 fn get_p2pkh_input_public_key(input: &TxIn, prevout_spk: &ScriptBuf) -> Result<PublicKey> {
     let script_sig_bytes = input.script_sig.as_bytes();
     // DUP HASH160 <public-key-hash> EQUALVERIFY CHECKSIG
     // <public-key-hash> is data so there is actually one length byte before it. So we skip first 3
     // bytes and take 20 byte slice.
     let public_key_hash = &prevout_spk.as_bytes()[3..23];
-    for i in 0..script_sig_bytes.len() {
-        if i + 33 > script_sig_bytes.len() {
-            break;
-        }
-        let frame = &script_sig_bytes[i..i + 33];
-        let frame_hash = bitcoin::hashes::hash160::Hash::hash(frame).to_byte_array();
-        if public_key_hash == frame_hash {
-            // Frame bytes are public key bytes.
-            let public_key =
-                PublicKey::from_slice(frame).expect("Frame bytes are valid public key bytes.");
-            return Ok(public_key);
-        }
-    }
-    Err(Error::InvalidInput)
+    let frame = script_sig_bytes
+        .windows(33)
+        .rev()
+        .find(|frame| {
+            bitcoin::hashes::hash160::Hash::hash(frame).as_byte_array() == public_key_hash
+        })
+        .ok_or(Error::InvalidInput)?;
+    // The hash matched but the bytes do not have to be a valid point.
+    PublicKey::from_slice(frame).map_err(|_| Error::InvalidInput)
 }
 
 fn get_p2wpkh_input_public_key(witness: &Witness) -> Result<PublicKey> {
     let key_bytes = witness.nth(1);
-    if let Some(bytes) = key_bytes {
-        if bytes.len() == 33 {
-            let public_key = PublicKey::from_slice(bytes)
-                .expect("Compressed Public Key bytes from witness are valid.");
-            return Ok(public_key);
-        }
+    if let Some(bytes) = key_bytes
+        && bytes.len() == 33
+    {
+        return PublicKey::from_slice(bytes).map_err(|_| Error::InvalidInput);
     }
     Err(Error::InvalidInput)
 }
 
 fn get_p2sh_p2wpkh_input_public_key(input: &TxIn, witness: &Witness) -> Result<PublicKey> {
-    let witness_program_bytes = &input.script_sig.as_bytes()[1..];
+    let witness_program_bytes = input
+        .script_sig
+        .as_bytes()
+        .get(1..)
+        .ok_or(Error::InvalidInput)?;
     let witness_program_script = ScriptBuf::from_bytes(witness_program_bytes.to_vec());
 
     if witness_program_script.is_p2wpkh() {
@@ -161,36 +215,47 @@ fn get_p2sh_p2wpkh_input_public_key(input: &TxIn, witness: &Witness) -> Result<P
     Err(Error::InvalidInput)
 }
 
-// Get the public key of a taproot input. Perform validation as defined in BIP-341 Specification,
-// Script validation rules (incomplete, we don't check the signature). Input has to use Key spend
-// path otherwise error is returned.
+// BIP-341 NUMS point H. Taproot outputs with this internal key can only be spent via script path.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+// Get the public key of a taproot input as defined in BIP-352. The taproot output key is used for
+// key path and script path spends, except for script path spends whose internal key is the NUMS
+// point H, those are skipped. Witness structure follows BIP-341 Specification, Script validation
+// rules (incomplete, we don't check the signature or the script).
 fn get_p2tr_input_public_key(input: &TxIn, prevout_spk: &ScriptBuf) -> Result<PublicKey> {
+    let witness = &input.witness;
+
     // Fail if the witness stack has 0 elements.
-    if input.witness.len() == 0 {
+    if witness.is_empty() {
         return Err(Error::InvalidInput);
     }
 
     // If there are at least two witness elements, and the first byte of the last element is 0x50,
     // this last element is called annex and is removed from the witness stack. (...).
-    if input.witness.len() >= 2 {
-        if let Some(_annex) = input.witness.taproot_annex() {
-            // If there is exactly one element left in the witness stack, key path spending is used.
-            if input.witness.len() != 2 {
-                return Err(Error::InvalidInput);
-            }
-            // TODO: Check valid signature for "q" (See BIP-341).
-        } else {
-            // More than one elements on witness stack but no annex means script path spend is used
-            // which we regard as invalid in this context.
+    let stack_len = match witness.taproot_annex() {
+        Some(_annex) => witness.len() - 1,
+        None => witness.len(),
+    };
+
+    // If there are at least two witness elements left, script path spending is used and the last
+    // element is the control block, which contains the internal key.
+    if stack_len >= 2 {
+        let control_block_bytes = witness.taproot_control_block().ok_or(Error::InvalidInput)?;
+        let control_block =
+            ControlBlock::decode(control_block_bytes).map_err(|_| Error::InvalidInput)?;
+        if control_block.internal_key.serialize() == NUMS_H {
             return Err(Error::InvalidInput);
         }
     }
 
-    // Key path spend is used.
     // scriptPubKey is OP_1 0x20 <32 byte x-only public key> so we take the slice without the OP_1
     // and 0x20.
     let pubkey_bytes = &prevout_spk.as_bytes()[2..];
-    let x_only_public_key = XOnlyPublicKey::from_slice(pubkey_bytes).unwrap();
+    let x_only_public_key =
+        XOnlyPublicKey::from_slice(pubkey_bytes).map_err(|_| Error::InvalidInput)?;
     let public_key = PublicKey::from_x_only_public_key(x_only_public_key, Parity::Even);
     Ok(public_key)
 }
@@ -4,8 +4,9 @@ use silent_payments_server::server::Server;
 
 use silent_payments_server::Result;
 use silent_payments_server::store::Store;
-use silent_payments_server::sync::Syncer;
-use tracing::info;
+use silent_payments_server::sync::{BlockFiles, Syncer};
+use silent_payments_server::wallets::WalletScanner;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
@@ -24,22 +25,42 @@ async fn main() -> Result<()> {
     let rpcurl = cfg.syncer.rpc_url.clone();
     let rpcuser = cfg.syncer.rpc_user.clone();
     let rpcpass = cfg.syncer.rpc_pass.clone();
-    let auth = Auth::UserPass(rpcuser.into(), rpcpass.into());
+    let auth = Auth::UserPass(rpcuser, rpcpass);
     let client = Client::new(&rpcurl, auth)?;
 
-    // Run syncer.
+    // Run syncer, catching up from block files first if configured.
     info!("Running syncer in task");
+    let catch_up_cfg = cfg.syncer.clone();
+    let catch_up_db = db.clone();
     let mut syncer = Syncer::new(cfg.syncer, client, db.clone());
-    tokio::task::spawn(async move { syncer.sync_from().await });
+    tokio::task::spawn(async move {
+        if let Some(blocks_dir) = catch_up_cfg.blocks_dir.clone() {
+            match BlockFiles::open(&blocks_dir) {
+                Ok(block_files) => {
+                    info!("Catching up from block files.");
+                    let mut catch_up = Syncer::new(catch_up_cfg, block_files, catch_up_db);
+                    if let Err(err) = catch_up.catch_up().await {
+                        warn!("Catching up from block files failed: {}", err);
+                    }
+                }
+                Err(err) => warn!("Could not open block files: {}", err),
+            }
+        }
+        syncer.sync_from().await
+    });
+
+    // Scan the blocks for the outputs of registered wallets.
+    info!("Running wallet scanner in task.");
+    tokio::task::spawn(WalletScanner::new(db.clone()).run());
 
-    // Subscribe blocks that were added to DB.
+    // Subscribe blocks that were added to or removed from DB.
     info!("Subscibing to blocks in task.");
     let sub_db = db.clone();
     tokio::task::spawn(async move {
         let mut rx = sub_db.subscribe_blocks();
         loop {
             match rx.recv().await {
-                Ok(block) => info!("new block: {:?}", block),
+                Ok(event) => info!("block event: {:?}", event),
 
                 Err(_) => {
                     info!("Sender (store) dropped.");
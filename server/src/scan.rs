@@ -0,0 +1,356 @@
+// Receiver side of BIP-352. The server stores the public tweak data `input_hash * A` of every
+// transaction, so a receiver only needs the scan secret key and the spend public key to find its
+// outputs:
+//
+//   shared_secret = b_scan * input_hash * A
+//   t_k           = hash_BIP0352/SharedSecret(shared_secret || ser32(k))
+//   P_k           = B_spend + t_k * G
+//
+// Outputs matching P_k are owned by the receiver, who spends them with `b_spend + t_k`. Starting
+// at k = 0, k is incremented after every match until no output matches anymore or `K_MAX` outputs
+// matched.
+//
+// Labels let a receiver tell payments apart while keeping the scan key, the spend key of a labeled
+// address is
+//
+//   label = hash_BIP0352/Label(b_scan || ser32(m))
+//   B_m   = B_spend + label * G
+//
+// and outputs to it are spent with `b_spend + t_k + label`. Instead of deriving P_k for every label,
+// `output - P_k` and `-output - P_k` are looked up in a table of `label * G`. The change label
+// m = 0 is always scanned for.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use secp256k1::{
+    All, Parity, PublicKey, Scalar, Secp256k1, SecretKey, Verification, XOnlyPublicKey,
+};
+use serde::Serialize;
+
+use crate::store::model::{Output, Transaction};
+use crate::{Error, P2TR_PREFIX, Result, TweakError, tagged_hash};
+
+// Maximum number of outputs a sender may create for one recipient, receivers do not scan beyond it.
+const K_MAX: u32 = 2323;
+// Label reserved for change, senders use it to send change to themselves.
+pub const CHANGE_LABEL: u32 = 0;
+
+// Taproot output of a transaction owned by the receiver.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OwnedOutput {
+    pub txid: String,
+    pub vout: i64,
+    pub value: i64,
+    // Hex encoded x-only output key.
+    pub pub_key: String,
+    // Hex encoded tweak added to the spend secret key to get the output's secret key, includes the
+    // label tweak for outputs to labeled addresses.
+    pub tweak: String,
+    // Label of the address the output was sent to, `None` for the unlabeled address.
+    pub label: Option<u32>,
+}
+
+pub struct Scanner {
+    secp: Secp256k1<All>,
+    scan_key: SecretKey,
+    spend_key: PublicKey,
+    // label * G -> (m, label)
+    labels: HashMap<PublicKey, (u32, SecretKey)>,
+}
+
+impl Scanner {
+    // Scanner for the unlabeled address and the addresses of `labels`, the change label is added
+    // if missing.
+    pub fn new(scan_key: SecretKey, spend_key: PublicKey, labels: &[u32]) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let mut table = HashMap::new();
+        for m in labels.iter().copied().chain([CHANGE_LABEL]) {
+            let label = label_tweak(&scan_key, m)?;
+            table.insert(label.public_key(&secp), (m, label));
+        }
+        Ok(Self {
+            secp,
+            scan_key,
+            spend_key,
+            labels: table,
+        })
+    }
+
+    // Outputs of the transactions owned by the receiver. Transactions that have to be skipped
+    // according to BIP-352 are ignored.
+    pub fn scan_transactions(&self, transactions: &[Transaction]) -> Result<Vec<OwnedOutput>> {
+        let mut owned = vec![];
+        for tx in transactions {
+            match self.scan_transaction(tx) {
+                Ok(outputs) => owned.extend(outputs),
+                Err(Error::Tweak(_)) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(owned)
+    }
+
+    pub fn scan_transaction(&self, tx: &Transaction) -> Result<Vec<OwnedOutput>> {
+        let tweak = PublicKey::from_str(&tx.scalar).map_err(|_| Error::InvalidInput)?;
+        let shared_secret = tweak
+            .mul_tweak(&self.secp, &Scalar::from(self.scan_key))
+            .expect("secret keys are valid non-zero scalars");
+
+        // Outputs that are not matched yet, with their x-only keys.
+        let mut outputs = vec![];
+        for output in tx.outputs.iter() {
+            let spk = hex::decode(&output.spk)?;
+            if let Some(key) = spk.strip_prefix(&P2TR_PREFIX)
+                && let Ok(key) = XOnlyPublicKey::from_slice(key)
+            {
+                outputs.push((output, key));
+            }
+        }
+
+        let mut owned = vec![];
+        for k in 0..K_MAX {
+            if outputs.is_empty() {
+                break;
+            }
+            let t_k = shared_secret_hash(&shared_secret, k)?;
+            let p_k = self
+                .spend_key
+                .add_exp_tweak(&self.secp, &Scalar::from(t_k))
+                .map_err(|_| TweakError::InvalidSharedSecretHash)?;
+
+            // Unlabeled outputs are looked for first, they are cheaper to match.
+            let (x_only_p_k, _parity) = p_k.x_only_public_key();
+            let Some((index, label)) = outputs
+                .iter()
+                .position(|(_, key)| *key == x_only_p_k)
+                .map(|index| (index, None))
+                .or_else(|| {
+                    let (index, label) = self.match_label(&outputs, &p_k)?;
+                    Some((index, Some(label)))
+                })
+            else {
+                break;
+            };
+            let tweak = match label {
+                Some((_, label)) => t_k
+                    .add_tweak(&Scalar::from(label))
+                    .map_err(|_| TweakError::InvalidSharedSecretHash)?,
+                None => t_k,
+            };
+            let (output, key) = outputs.swap_remove(index);
+            owned.push(OwnedOutput {
+                txid: tx.txid.clone(),
+                vout: output.vout,
+                value: output.value,
+                pub_key: key.to_string(),
+                tweak: hex::encode(tweak.secret_bytes()),
+                label: label.map(|(m, _)| m),
+            });
+        }
+        Ok(owned)
+    }
+
+    // Index of the output that is P_k tweaked with one of the labels, and the label. Depending on
+    // which is fewer, either P_k is tweaked with every label or P_k is subtracted from every
+    // output. Output keys are x-only, so in the latter case the label may have been added to either
+    // of the two points with the output's x coordinate.
+    fn match_label(
+        &self,
+        outputs: &[(&Output, XOnlyPublicKey)],
+        p_k: &PublicKey,
+    ) -> Option<(usize, (u32, SecretKey))> {
+        if self.labels.len() <= outputs.len() {
+            return self.labels.iter().find_map(|(label_point, label)| {
+                let (key, _parity) = p_k.combine(label_point).ok()?.x_only_public_key();
+                let index = outputs
+                    .iter()
+                    .position(|(_, output_key)| *output_key == key)?;
+                Some((index, *label))
+            });
+        }
+        let negated_p_k = p_k.negate(&self.secp);
+        outputs.iter().enumerate().find_map(|(index, (_, key))| {
+            let output = PublicKey::from_x_only_public_key(*key, Parity::Even);
+            [output, output.negate(&self.secp)]
+                .into_iter()
+                .filter_map(|output| output.combine(&negated_p_k).ok())
+                .find_map(|label_point| self.labels.get(&label_point))
+                .map(|label| (index, *label))
+        })
+    }
+
+    // Spend key B_m of the address with label m.
+    pub fn labeled_spend_key(&self, m: u32) -> Result<PublicKey> {
+        labeled_spend_key(&self.secp, &self.scan_key, &self.spend_key, m)
+    }
+}
+
+// label = hash_BIP0352/Label(ser256(b_scan) || ser32(m))
+pub fn label_tweak(scan_key: &SecretKey, m: u32) -> Result<SecretKey> {
+    let msg = [scan_key.secret_bytes().as_slice(), &m.to_be_bytes()].concat();
+    let hash = tagged_hash(b"BIP0352/Label", &msg);
+    SecretKey::from_byte_array(&hash).map_err(|_| TweakError::InvalidLabelHash.into())
+}
+
+// B_m = B_spend + label * G
+pub fn labeled_spend_key<C: Verification>(
+    secp: &Secp256k1<C>,
+    scan_key: &SecretKey,
+    spend_key: &PublicKey,
+    m: u32,
+) -> Result<PublicKey> {
+    let label = label_tweak(scan_key, m)?;
+    spend_key
+        .add_exp_tweak(secp, &Scalar::from(label))
+        .map_err(|_| TweakError::InvalidLabelHash.into())
+}
+
+// t_k = hash_BIP0352/SharedSecret(serP(shared_secret) || ser32(k))
+fn shared_secret_hash(shared_secret: &PublicKey, k: u32) -> Result<SecretKey> {
+    let msg = [shared_secret.serialize().as_slice(), &k.to_be_bytes()].concat();
+    let hash = tagged_hash(b"BIP0352/SharedSecret", &msg);
+    SecretKey::from_byte_array(&hash).map_err(|_| TweakError::InvalidSharedSecretHash.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::address::{Network, SilentPaymentAddress};
+    use crate::tests::bip352::receiving_cases;
+
+    fn transaction(scalar: &str, keys: &[&str]) -> Transaction {
+        Transaction {
+            txid: "00".repeat(32),
+            scalar: scalar.into(),
+            position: 0,
+            outputs: keys
+                .iter()
+                .enumerate()
+                .map(|(vout, key)| Output {
+                    vout: vout as i64,
+                    value: 1000,
+                    spk: format!("5120{key}"),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_scan_vectors() {
+        let secp = Secp256k1::new();
+        for case in receiving_cases() {
+            // Transactions without tweak are not indexed.
+            let Some(scalar) = case.expected["tweak"].as_str() else {
+                continue;
+            };
+            let key_material = &case.given["key_material"];
+            let scan_key =
+                SecretKey::from_str(key_material["scan_priv_key"].as_str().unwrap()).unwrap();
+            let spend_key =
+                SecretKey::from_str(key_material["spend_priv_key"].as_str().unwrap()).unwrap();
+            let labels: Vec<u32> = case.given["labels"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| m.as_u64().unwrap() as u32)
+                .collect();
+            let scanner = Scanner::new(scan_key, spend_key.public_key(&secp), &labels).unwrap();
+
+            // The unlabeled address followed by the labeled ones, in any order.
+            let expected_addresses = case.expected["addresses"].as_array().unwrap();
+            assert_eq!(
+                expected_addresses.len(),
+                labels.len() + 1,
+                "{}",
+                case.comment
+            );
+            for m in labels.iter().copied() {
+                let address = SilentPaymentAddress::new(
+                    scan_key.public_key(&secp),
+                    scanner.labeled_spend_key(m).unwrap(),
+                    Network::Mainnet,
+                );
+                assert!(
+                    expected_addresses.contains(&address.to_string().into()),
+                    "{}: label {m}",
+                    case.comment
+                );
+            }
+
+            let keys: Vec<&str> = case.given["outputs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|key| key.as_str().unwrap())
+                .collect();
+            let owned = scanner
+                .scan_transactions(&[transaction(scalar, &keys)])
+                .unwrap();
+
+            let actual: HashSet<(String, String)> = owned
+                .into_iter()
+                .map(|output| (output.pub_key, output.tweak))
+                .collect();
+            // Vectors with many outputs only have their number.
+            if let Some(n_outputs) = case.expected["n_outputs"].as_u64() {
+                assert_eq!(actual.len() as u64, n_outputs, "{}", case.comment);
+                continue;
+            }
+            let expected: HashSet<(String, String)> = case.expected["outputs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|output| {
+                    (
+                        output["pub_key"].as_str().unwrap().to_string(),
+                        output["priv_key_tweak"].as_str().unwrap().to_string(),
+                    )
+                })
+                .collect();
+            assert_eq!(actual, expected, "{}", case.comment);
+        }
+    }
+
+    #[test]
+    fn test_spend_with_tweak() {
+        let secp = Secp256k1::new();
+        let scan_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let spend_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let scanner = Scanner::new(scan_key, spend_key.public_key(&secp), &[]).unwrap();
+
+        // Outputs to the receiver for k = 0 and k = 1, in reverse order and next to an unrelated one.
+        let scalar = SecretKey::from_slice(&[3; 32]).unwrap().public_key(&secp);
+        let shared_secret = scalar.mul_tweak(&secp, &Scalar::from(scan_key)).unwrap();
+        let output_key = |k| {
+            let t_k = shared_secret_hash(&shared_secret, k).unwrap();
+            let secret_key = spend_key.add_tweak(&Scalar::from(t_k)).unwrap();
+            secret_key.x_only_public_key(&secp).0.to_string()
+        };
+        let unrelated = SecretKey::from_slice(&[4; 32])
+            .unwrap()
+            .x_only_public_key(&secp)
+            .0
+            .to_string();
+        let tx = transaction(
+            &scalar.to_string(),
+            &[&output_key(1), &unrelated, &output_key(0)],
+        );
+
+        let owned = scanner.scan_transaction(&tx).unwrap();
+        assert_eq!(
+            owned.iter().map(|output| output.vout).collect::<Vec<_>>(),
+            [2, 0]
+        );
+        for output in owned {
+            let tweak = SecretKey::from_str(&output.tweak).unwrap();
+            let secret_key = spend_key.add_tweak(&Scalar::from(tweak)).unwrap();
+            assert_eq!(
+                secret_key.x_only_public_key(&secp).0.to_string(),
+                output.pub_key
+            );
+        }
+    }
+}
@@ -1,16 +1,32 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
 use axum::{
     Json,
-    extract::{Path, State, WebSocketUpgrade, ws::Message},
+    extract::{State, WebSocketUpgrade, ws::Message},
+    http::{HeaderMap, HeaderValue, header},
     response::{IntoResponse, Response},
 };
+use bitcoin::hashes::{Hash, sha256};
+use bitcoincore_rpc::bitcoin::Txid;
 use futures::{Sink, SinkExt, Stream, StreamExt};
+use secp256k1::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::sync::{Semaphore, broadcast::error::RecvError};
+use tracing::{error, warn};
 
+use super::extract::{ApiJson, ApiPath, ApiQuery};
+use super::wire;
 use crate::{
     Error,
     store::{
         Store,
-        model::{Block, Scalars, Transactions},
+        model::{
+            Block, BlockEvent, BlockFilter, BlockRange, BlockScalars, BlockTransactions,
+            DisconnectedBlock, FilterKind, MempoolEvent, TransactionFilter, Transactions, Wallet,
+            WalletEvent, WalletId,
+        },
     },
 };
 
@@ -20,64 +36,401 @@ pub async fn root() -> &'static str {
     "Silent Payment Server"
 }
 
-// GET /blocks/latest/scalars
-pub async fn get_latest_scalars(State(db): State<Store>) -> Result<Json<Scalars>> {
-    let scalars = db.get_latest_scalars().await?;
-    Ok(Json(scalars))
+fn parse_height(height: i64) -> Result<i64> {
+    if height < 0 {
+        return Err(Error::BadRequest(format!("invalid height: {height}")));
+    }
+    Ok(height)
+}
+
+// Txids are normalized so differently cased txids find the same transaction.
+fn parse_txid(txid: &str) -> Result<String> {
+    Txid::from_str(txid)
+        .map(|txid| txid.to_string())
+        .map_err(|_| Error::BadRequest(format!("invalid txid: {txid}")))
+}
+
+// Quality of `media_type` in an Accept header, 0 if the client does not accept it. Only exact
+// matches count for the binary format, JSON is also matched by wildcards.
+fn accept_quality(accept: &str, media_type: &str) -> f32 {
+    let (main_type, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let range_type = params.next()?.to_ascii_lowercase();
+            let matches = range_type == media_type
+                || (media_type != wire::CONTENT_TYPE
+                    && (range_type == "*/*" || range_type == format!("{main_type}/*")));
+            if !matches {
+                return None;
+            }
+            // Invalid q-values exclude the media range like q=0.
+            let quality = params
+                .find_map(|param| param.strip_prefix("q=").or(param.strip_prefix("Q=")))
+                .map_or(Some(1.0), |q| {
+                    q.parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q))
+                })
+                .unwrap_or(0.0);
+            Some(quality)
+        })
+        .fold(0.0, f32::max)
+}
+
+// Respond with the binary wire format if the client prefers it, with JSON otherwise. Both responses
+// vary on the Accept header.
+fn negotiate<T: Serialize>(
+    headers: &HeaderMap,
+    value: T,
+    encode: impl FnOnce(&T) -> Result<Vec<u8>>,
+) -> Result<Response> {
+    let vary = (header::VARY, HeaderValue::from_static("accept"));
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .unwrap_or_default();
+    let binary_quality = accept_quality(accept, wire::CONTENT_TYPE);
+    if binary_quality > 0.0 && binary_quality >= accept_quality(accept, "application/json") {
+        let body = encode(&value)?;
+        let content_type = (
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(wire::CONTENT_TYPE),
+        );
+        return Ok(([content_type, vary], body).into_response());
+    }
+    Ok(([vary], Json(value)).into_response())
+}
+
+// Error for a block that is not in the store. It might still be synced while the index did not reach
+// the node's tip yet.
+fn block_not_found(db: &Store) -> Error {
+    if db.is_synced() {
+        Error::NotFound
+    } else {
+        Error::Syncing
+    }
+}
+
+// Error for a missing filter. Blocks below the backfill height might be stored without filters.
+async fn filter_not_found(db: &Store, height: i64) -> Result<Error> {
+    if height < db.get_backfill_height().await? {
+        return Ok(Error::Syncing);
+    }
+    Ok(block_not_found(db))
+}
+
+// Query parameters of block scoped scalar and transaction endpoints. Transactions whose taproot
+// outputs are all below `dust_limit` (in sats) are omitted, with `cut_through` also transactions
+// whose taproot outputs are all spent.
+#[derive(Deserialize)]
+pub struct FilterQuery {
+    dust_limit: Option<u64>,
+    cut_through: Option<bool>,
+}
+
+impl From<FilterQuery> for TransactionFilter {
+    fn from(query: FilterQuery) -> Self {
+        Self {
+            dust_limit: query
+                .dust_limit
+                .map_or(0, |limit| i64::try_from(limit).unwrap_or(i64::MAX)),
+            cut_through: query.cut_through.unwrap_or(false),
+        }
+    }
 }
 
-// GET /blocks/height/<height>/scalars
+// Cut-through omits transactions by the spends of later blocks, which are incomplete for blocks below
+// the backfill height.
+async fn check_cut_through(db: &Store, filter: &TransactionFilter, height: i64) -> Result<()> {
+    if filter.cut_through && height < db.get_backfill_height().await? {
+        return Err(Error::Syncing);
+    }
+    Ok(())
+}
+
+// Maximum number of blocks returned per page by the height range endpoints.
+const MAX_RANGE_BLOCKS: i64 = 1000;
+// Pages of the height range endpoints end before the block at which the transactions of the page add
+// up to more than this, but hold at least one block.
+const MAX_RANGE_TRANSACTIONS: i64 = 10_000;
+
+// Query parameters of height range endpoints. Pages hold at most `limit` blocks and end at the synced
+// height, clients continue with `from` set to the `next` height of the response.
+#[derive(Deserialize)]
+pub struct RangeQuery {
+    from: i64,
+    to: i64,
+    limit: Option<i64>,
+}
+
+impl RangeQuery {
+    // Last height of the page and the height the next page starts at. A page starting above the
+    // synced height is empty and points at its own start once synced, so clients wait for new blocks.
+    async fn page(&self, db: &Store) -> Result<(i64, Option<i64>)> {
+        parse_height(self.from)?;
+        if self.to < self.from {
+            return Err(Error::BadRequest(format!(
+                "invalid range: from {} to {}",
+                self.from, self.to
+            )));
+        }
+        let limit = self
+            .limit
+            .unwrap_or(MAX_RANGE_BLOCKS)
+            .clamp(1, MAX_RANGE_BLOCKS);
+        let synced = db.get_synced_blocks_height().await?.unwrap_or(-1);
+        if self.from > synced && !db.is_synced() {
+            return Err(Error::Syncing);
+        }
+        let page_to = self.to.min(synced).min(self.from.saturating_add(limit - 1));
+        let next = (page_to < self.to).then_some((page_to + 1).max(self.from));
+        match db
+            .get_height_exceeding_transactions(self.from, page_to, MAX_RANGE_TRANSACTIONS)
+            .await?
+        {
+            Some(height) if height > self.from => Ok((height - 1, Some(height))),
+            _ => Ok((page_to, next)),
+        }
+    }
+}
+
+// GET /blocks/scalars?from=<height>&to=<height>&limit=<blocks>
+pub async fn get_scalars_range(
+    State(db): State<Store>,
+    ApiQuery(range): ApiQuery<RangeQuery>,
+    ApiQuery(filter): ApiQuery<FilterQuery>,
+) -> Result<Json<BlockRange<BlockScalars>>> {
+    let (page_to, next) = range.page(&db).await?;
+    let filter = filter.into();
+    check_cut_through(&db, &filter, range.from).await?;
+    let blocks = db
+        .get_scalars_by_height_range(range.from, page_to, filter)
+        .await?;
+    Ok(Json(BlockRange { blocks, next }))
+}
+
+// GET /blocks/transactions?from=<height>&to=<height>&limit=<blocks>
+pub async fn get_transactions_range(
+    State(db): State<Store>,
+    ApiQuery(range): ApiQuery<RangeQuery>,
+    ApiQuery(filter): ApiQuery<FilterQuery>,
+) -> Result<Json<BlockRange<BlockTransactions>>> {
+    let (page_to, next) = range.page(&db).await?;
+    let filter = filter.into();
+    check_cut_through(&db, &filter, range.from).await?;
+    let blocks = db
+        .get_transactions_by_height_range(range.from, page_to, filter)
+        .await?;
+    Ok(Json(BlockRange { blocks, next }))
+}
+
+// GET /blocks/latest/scalars?dust_limit=<sats>&cut_through=<bool>
+pub async fn get_latest_scalars(
+    State(db): State<Store>,
+    headers: HeaderMap,
+    ApiQuery(filter): ApiQuery<FilterQuery>,
+) -> Result<Response> {
+    let filter = filter.into();
+    let scalars = db.get_latest_scalars(filter).await?.ok_or(Error::Syncing)?;
+    check_cut_through(&db, &filter, scalars.height).await?;
+    negotiate(&headers, scalars, wire::encode_scalars)
+}
+
+// GET /blocks/height/<height>/scalars?dust_limit=<sats>&cut_through=<bool>
 pub async fn get_scalars(
     State(db): State<Store>,
-    Path(height): Path<i64>,
-) -> Result<Json<Scalars>> {
-    let scalars = db.get_scalars_by_height(height).await?;
-    Ok(Json(scalars))
+    headers: HeaderMap,
+    ApiPath(height): ApiPath<i64>,
+    ApiQuery(filter): ApiQuery<FilterQuery>,
+) -> Result<Response> {
+    let height = parse_height(height)?;
+    let filter = filter.into();
+    check_cut_through(&db, &filter, height).await?;
+    let scalars = db
+        .get_scalars_by_height(height, filter)
+        .await?
+        .ok_or_else(|| block_not_found(&db))?;
+    negotiate(&headers, scalars, wire::encode_scalars)
 }
 
 // GET /transactions/<txid>/scalar
 pub async fn get_scalar(
     State(db): State<Store>,
-    Path(txid): Path<String>,
+    ApiPath(txid): ApiPath<String>,
 ) -> Result<impl IntoResponse> {
-    db.get_scalar_by_txid(txid)
+    db.get_scalar_by_txid(parse_txid(&txid)?)
         .await?
         .map(Json)
         .ok_or_else(|| Error::NotFound)
 }
 
-// GET /blocks/latest/transactions
-pub async fn get_latest_transactions(State(db): State<Store>) -> Result<Json<Transactions>> {
-    let transactions = db.get_latest_transactions().await?;
-    Ok(Json(transactions))
+// GET /blocks/latest/transactions?dust_limit=<sats>&cut_through=<bool>
+pub async fn get_latest_transactions(
+    State(db): State<Store>,
+    headers: HeaderMap,
+    ApiQuery(filter): ApiQuery<FilterQuery>,
+) -> Result<Response> {
+    let filter = filter.into();
+    let transactions = db
+        .get_latest_transactions(filter)
+        .await?
+        .ok_or(Error::Syncing)?;
+    check_cut_through(&db, &filter, transactions.height).await?;
+    negotiate(&headers, transactions, wire::encode_transactions)
 }
-// GET /blocks/height/<height>/transactions
+
+// GET /blocks/height/<height>/transactions?dust_limit=<sats>&cut_through=<bool>
 pub async fn get_transactions(
     State(db): State<Store>,
-    Path(height): Path<i64>,
-) -> Result<Json<Transactions>> {
-    let transactions = db.get_transactions_by_height(height).await?;
-    Ok(Json(transactions))
+    headers: HeaderMap,
+    ApiPath(height): ApiPath<i64>,
+    ApiQuery(filter): ApiQuery<FilterQuery>,
+) -> Result<Response> {
+    let height = parse_height(height)?;
+    let filter = filter.into();
+    check_cut_through(&db, &filter, height).await?;
+    let transactions = db
+        .get_transactions_by_height(height, filter)
+        .await?
+        .ok_or_else(|| block_not_found(&db))?;
+    negotiate(&headers, transactions, wire::encode_transactions)
+}
+
+// GET /blocks/height/<height>/filter/new-utxos
+pub async fn get_new_utxos_filter(
+    State(db): State<Store>,
+    ApiPath(height): ApiPath<i64>,
+) -> Result<Json<BlockFilter>> {
+    let height = parse_height(height)?;
+    match db.get_filter(height, FilterKind::NewUtxos).await? {
+        Some(filter) => Ok(Json(filter)),
+        None => Err(filter_not_found(&db, height).await?),
+    }
+}
+
+// GET /blocks/height/<height>/filter/spent
+pub async fn get_spent_filter(
+    State(db): State<Store>,
+    ApiPath(height): ApiPath<i64>,
+) -> Result<Json<BlockFilter>> {
+    let height = parse_height(height)?;
+    match db.get_filter(height, FilterKind::Spent).await? {
+        Some(filter) => Ok(Json(filter)),
+        None => Err(filter_not_found(&db, height).await?),
+    }
 }
 
 // GET /transactions/<txid>
 pub async fn get_transaction(
     State(db): State<Store>,
-    Path(txid): Path<String>,
+    ApiPath(txid): ApiPath<String>,
 ) -> Result<impl IntoResponse> {
-    db.get_transaction_by_txid(txid)
+    db.get_transaction_by_txid(parse_txid(&txid)?)
         .await?
         .map(Json)
         .ok_or_else(|| Error::NotFound)
 }
 
+// GET /mempool/transactions
+pub async fn get_mempool_transactions(State(db): State<Store>) -> Result<Json<Transactions>> {
+    let transactions = db.get_mempool_transactions().await?;
+    Ok(Json(transactions))
+}
+
+// GET /outputs/<txid>/<vout>
+pub async fn get_output(
+    State(db): State<Store>,
+    ApiPath((txid, vout)): ApiPath<(String, u32)>,
+) -> Result<impl IntoResponse> {
+    let output = db
+        .get_output(parse_txid(&txid)?, vout.into())
+        .await?
+        .ok_or(Error::NotFound)?;
+    // Spends of outputs in blocks that were not backfilled yet might be missing.
+    if output.spent.is_none() && output.height < db.get_backfill_height().await? {
+        return Err(Error::Syncing);
+    }
+    Ok(Json(output))
+}
+
+// Limits on wallets, every registered wallet is scanned and subscribed wallets are streamed to.
+#[derive(Clone)]
+pub struct WalletLimits {
+    // Lowest birthday accepted for registrations.
+    min_birthday: i64,
+    // Maximum number of registered wallets.
+    max_wallets: i64,
+    // Permits for concurrent wallet subscriptions.
+    subscriptions: Arc<Semaphore>,
+}
+
+impl WalletLimits {
+    pub fn new(min_birthday: i64, max_wallets: i64, max_subscriptions: usize) -> Self {
+        Self {
+            min_birthday,
+            max_wallets,
+            subscriptions: Arc::new(Semaphore::new(max_subscriptions)),
+        }
+    }
+}
+
+// Body of wallet registrations. Wallets are watch-only, unknown fields are rejected so a spend
+// secret key sent by mistake is not silently accepted.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterWallet {
+    scan_key: String,
+    spend_key: String,
+    birthday: i64,
+    labels: Option<Vec<u32>>,
+}
+
+// POST /wallet
+pub async fn register_wallet(
+    State(db): State<Store>,
+    State(limits): State<WalletLimits>,
+    ApiJson(body): ApiJson<RegisterWallet>,
+) -> Result<Json<WalletId>> {
+    let scan_key = SecretKey::from_str(&body.scan_key)
+        .map_err(|_| Error::BadRequest("invalid scan key".into()))?;
+    let spend_key = PublicKey::from_str(&body.spend_key)
+        .map_err(|_| Error::BadRequest("invalid spend key".into()))?;
+    let birthday = parse_height(body.birthday)?;
+    if birthday < limits.min_birthday {
+        return Err(Error::BadRequest(format!(
+            "birthday below minimum height {}",
+            limits.min_birthday
+        )));
+    }
+    let mut labels = body.labels.unwrap_or_default();
+    labels.sort_unstable();
+    labels.dedup();
+
+    // The id is derived from the keys so it cannot be guessed without the scan key, and
+    // registering the same keys again returns the same id.
+    let keys = [scan_key.secret_bytes().as_slice(), &spend_key.serialize()].concat();
+    let hash = sha256::Hash::hash(&keys);
+    let wallet = Wallet {
+        id: hex::encode(&hash[..16]),
+        scan_key: hex::encode(scan_key.secret_bytes()),
+        spend_key: spend_key.to_string(),
+        birthday,
+        labels,
+        scanned_height: None,
+    };
+    if db.get_wallet(wallet.id.clone()).await?.is_none()
+        && db.count_wallets().await? >= limits.max_wallets
+    {
+        return Err(Error::LimitReached("too many wallets registered".into()));
+    }
+    db.add_wallet(&wallet).await?;
+    Ok(Json(WalletId { id: wallet.id }))
+}
+
 // GET /blocks/tip
-pub async fn get_chain_tip(State(db): State<Store>) -> String {
-    db.get_synced_blocks_height()
-        .await
-        .unwrap()
-        .unwrap()
-        .to_string()
+pub async fn get_chain_tip(State(db): State<Store>) -> Result<String> {
+    let height = db.get_synced_blocks_height().await?.ok_or(Error::Syncing)?;
+    Ok(height.to_string())
 }
 
 // Websockets
@@ -111,26 +464,142 @@ pub async fn ws_subscribe_socket<W, R>(
     let ser_msg = match kind {
         SubscriptionKind::Scalars => |block: Block| {
             let scalars = block.transactions.into_iter().map(|tx| tx.scalar).collect();
-            json!(Scalars { scalars }).to_string()
+            json!(BlockScalars {
+                height: block.height,
+                block_hash: block.hash,
+                scalars,
+            })
+            .to_string()
         },
         SubscriptionKind::Transactions => |block: Block| {
-            let transactions = block.transactions;
-            json!(Transactions { transactions }).to_string()
+            json!(BlockTransactions {
+                height: block.height,
+                block_hash: block.hash,
+                transactions: block.transactions,
+            })
+            .to_string()
         },
     };
 
-    while let Ok(block) = rx.recv().await {
-        if block.transactions.is_empty() {
-            continue;
+    while let Ok(event) = rx.recv().await {
+        let msg = match event {
+            BlockEvent::Connected(block) => {
+                if block.transactions.is_empty() {
+                    continue;
+                }
+                ser_msg(block)
+            }
+            // Sent regardless of the subscription kind so clients can undo what they saw.
+            BlockEvent::Disconnected(block) => json!({
+                "disconnected": DisconnectedBlock {
+                    height: block.height,
+                    hash: block.hash,
+                }
+            })
+            .to_string(),
+        };
+        if write.send(Message::Text(msg.into())).await.is_err() {
+            break;
         }
+    }
+}
 
-        let msg = ser_msg(block);
+pub async fn ws_subscribe_mempool(state: State<Store>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(|socket| {
+        let (write, read) = socket.split();
+        ws_subscribe_mempool_socket(state, write, read)
+    })
+}
+
+pub async fn ws_subscribe_mempool_socket<W, R>(State(db): State<Store>, mut write: W, mut _read: R)
+where
+    W: Sink<Message> + Unpin,
+    R: Stream<Item = core::result::Result<Message, axum::Error>>,
+{
+    let mut rx = db.subscribe_mempool();
+
+    while let Ok(event) = rx.recv().await {
+        let msg = match event {
+            MempoolEvent::Added(transactions) => json!(Transactions { transactions }).to_string(),
+            // Confirmed or evicted, confirmed transactions are sent again by the block subscriptions.
+            MempoolEvent::Removed(txids) => json!({ "removed": txids }).to_string(),
+        };
         if write.send(Message::Text(msg.into())).await.is_err() {
             break;
         }
     }
 }
 
-// TODO:
-// POST /wallet (create wallet and return wallet id or mnemonic) (only use for development)
-// WS /ws/<wallet_id>/outputs: Stream outputs owned by wallet
+// WS /ws/<wallet_id>/outputs
+pub async fn ws_subscribe_wallet(
+    State(db): State<Store>,
+    State(limits): State<WalletLimits>,
+    ApiPath(id): ApiPath<String>,
+    ws: WebSocketUpgrade,
+) -> Result<Response> {
+    let wallet = db.get_wallet(id).await?.ok_or(Error::NotFound)?;
+    let permit = limits
+        .subscriptions
+        .try_acquire_owned()
+        .map_err(|_| Error::LimitReached("too many wallet subscriptions".into()))?;
+    Ok(ws.on_upgrade(move |socket| async move {
+        let (write, read) = socket.split();
+        ws_subscribe_wallet_socket(State(db), wallet, write, read).await;
+        drop(permit);
+    }))
+}
+
+// Streams the outputs owned by a wallet, grouped by block: the ones found so far on connect, then
+// new ones as the wallet scanner finds them. Blocks without owned outputs are not sent.
+pub async fn ws_subscribe_wallet_socket<W, R>(
+    State(db): State<Store>,
+    wallet: Wallet,
+    mut write: W,
+    mut _read: R,
+) where
+    W: Sink<Message> + Unpin,
+    R: Stream<Item = core::result::Result<Message, axum::Error>>,
+{
+    let (mut rx, stored) = match db.subscribe_wallet(&wallet.id).await {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            error!("Failed to read outputs of wallet {}: {}", wallet.id, err);
+            return;
+        }
+    };
+    for outputs in stored {
+        let msg = json!(outputs).to_string();
+        if write.send(Message::Text(msg.into())).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let msg = match rx.recv().await {
+            Ok(WalletEvent::Outputs(id, outputs)) if id == wallet.id => json!(outputs).to_string(),
+            Ok(WalletEvent::Disconnected(block)) if block.height >= wallet.birthday => {
+                json!({ "disconnected": block }).to_string()
+            }
+            Ok(WalletEvent::Registered(id)) if id == wallet.id => {
+                let msg = json!({ "error": "wallet was registered again, subscribe again" });
+                let _ = write.send(Message::Text(msg.to_string().into())).await;
+                break;
+            }
+            Ok(_) => continue,
+            // Missed outputs can not be told apart from the stored ones sent before.
+            Err(RecvError::Lagged(missed)) => {
+                warn!(
+                    "Wallet subscription {} missed {} events, closing it.",
+                    wallet.id, missed
+                );
+                let msg = json!({ "error": "missed outputs, subscribe again" });
+                let _ = write.send(Message::Text(msg.to_string().into())).await;
+                break;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if write.send(Message::Text(msg.into())).await.is_err() {
+            break;
+        }
+    }
+}
@@ -1,19 +1,45 @@
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::{Router, routing::get};
-use tracing::info;
+use axum::{
+    Json, Router,
+    extract::FromRef,
+    routing::{get, post},
+};
+use serde::Serialize;
+use tracing::{error, info};
 
 use crate::config::ServerConfig;
 use crate::store::Store;
 use crate::{Error, Result};
 
+mod extract;
 mod handler;
+pub mod wire;
 
 pub struct Server {
     cfg: ServerConfig,
     db: Store,
 }
 
+// State of the handlers, each extracts the parts it needs.
+#[derive(Clone)]
+struct AppState {
+    db: Store,
+    wallet_limits: handler::WalletLimits,
+}
+
+impl FromRef<AppState> for Store {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for handler::WalletLimits {
+    fn from_ref(state: &AppState) -> Self {
+        state.wallet_limits.clone()
+    }
+}
+
 impl Server {
     pub fn new(cfg: ServerConfig, db: Store) -> Self {
         Self { cfg, db }
@@ -21,7 +47,14 @@ impl Server {
 
     pub async fn run(&self) -> Result<()> {
         // SqlitePool is Arc<T>.
-        let state = self.db.clone();
+        let state = AppState {
+            db: self.db.clone(),
+            wallet_limits: handler::WalletLimits::new(
+                self.cfg.wallet_min_birthday,
+                self.cfg.max_wallets,
+                self.cfg.max_wallet_subscriptions,
+            ),
+        };
 
         let app = Router::new()
             .route("/", get(handler::root))
@@ -31,13 +64,28 @@ impl Server {
                 "/blocks/latest/transactions",
                 get(handler::get_latest_transactions),
             )
+            .route("/blocks/scalars", get(handler::get_scalars_range))
+            .route("/blocks/transactions", get(handler::get_transactions_range))
             .route("/blocks/height/{height}/scalars", get(handler::get_scalars))
             .route(
                 "/blocks/height/{height}/transactions",
                 get(handler::get_transactions),
             )
+            .route(
+                "/blocks/height/{height}/filter/new-utxos",
+                get(handler::get_new_utxos_filter),
+            )
+            .route(
+                "/blocks/height/{height}/filter/spent",
+                get(handler::get_spent_filter),
+            )
             .route("/transactions/{txid}", get(handler::get_transaction))
             .route("/transactions/{txid}/scalar", get(handler::get_scalar))
+            .route("/outputs/{txid}/{vout}", get(handler::get_output))
+            .route(
+                "/mempool/transactions",
+                get(handler::get_mempool_transactions),
+            )
             .route(
                 "/ws/scalars",
                 get(|state, ws| {
@@ -50,6 +98,9 @@ impl Server {
                     handler::ws_subscribe(state, ws, handler::SubscriptionKind::Transactions)
                 }),
             )
+            .route("/wallet", post(handler::register_wallet))
+            .route("/ws/mempool", get(handler::ws_subscribe_mempool))
+            .route("/ws/{wallet_id}/outputs", get(handler::ws_subscribe_wallet))
             .with_state(state);
 
         let host = format!("{}:{}", self.cfg.server_host, self.cfg.server_port);
@@ -61,11 +112,476 @@ impl Server {
     }
 }
 
+// JSON body of error responses. `code` is stable and meant for clients to match on, `message` is
+// meant for humans.
+#[derive(Serialize)]
+struct ErrorResponse {
+    code: &'static str,
+    message: String,
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        match self {
-            Error::NotFound => StatusCode::NOT_FOUND.into_response(),
-            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        let (status, code, message) = match self {
+            Error::NotFound => (StatusCode::NOT_FOUND, "not_found", "not found".into()),
+            Error::BadRequest(message) => (StatusCode::BAD_REQUEST, "bad_request", message),
+            Error::Syncing => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "syncing",
+                "index is still syncing".into(),
+            ),
+            Error::LimitReached(message) => {
+                (StatusCode::TOO_MANY_REQUESTS, "limit_reached", message)
+            }
+            err => {
+                // Internal errors are logged but not exposed to clients.
+                error!("Internal error while handling request: {}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal_error",
+                    "internal server error".into(),
+                )
+            }
+        };
+        (status, Json(ErrorResponse { code, message })).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::State;
+    use axum::http::{HeaderMap, HeaderValue, header};
+
+    use std::str::FromStr;
+
+    use axum::extract::ws::Message;
+    use futures::StreamExt;
+    use secp256k1::{Secp256k1, SecretKey};
+
+    use super::extract::{ApiJson, ApiPath, ApiQuery};
+    use super::handler::{FilterQuery, RegisterWallet, WalletLimits};
+    use crate::store::model::{Block, Output, Transaction, Wallet};
+    use crate::tests::bip352::receiving_cases;
+    use crate::tests::fixtures::memory_store;
+    use crate::wallets::WalletScanner;
+
+    use super::*;
+
+    async fn error_response(err: Error) -> (StatusCode, serde_json::Value) {
+        let response = err.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_error_response() {
+        let (status, body) = error_response(Error::BadRequest("invalid txid: x".into())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "bad_request");
+        assert_eq!(body["message"], "invalid txid: x");
+
+        let (status, body) = error_response(Error::Config).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "internal_error");
+    }
+
+    #[tokio::test]
+    async fn test_chain_tip_while_syncing() {
+        let store = memory_store().await;
+        let err = handler::get_chain_tip(State(store)).await.unwrap_err();
+        let (status, body) = error_response(err).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "syncing");
+    }
+
+    #[tokio::test]
+    async fn test_cut_through_while_backfilling() {
+        let store = memory_store().await;
+        for height in 1..=2 {
+            store
+                .add_block(Block {
+                    height,
+                    hash: height.to_string(),
+                    transactions: vec![],
+                    spent: vec![],
+                    filters: vec![],
+                })
+                .await
+                .unwrap();
+        }
+        store.set_backfill_height(2).await;
+        let query = |query: &str| ApiQuery(serde_json::from_str::<FilterQuery>(query).unwrap());
+        let cut_through = r#"{"cut_through": true}"#;
+
+        let err = handler::get_scalars(
+            State(store.clone()),
+            HeaderMap::new(),
+            ApiPath(1),
+            query(cut_through),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Syncing));
+        handler::get_scalars(
+            State(store.clone()),
+            HeaderMap::new(),
+            ApiPath(1),
+            query("{}"),
+        )
+        .await
+        .unwrap();
+        handler::get_scalars(
+            State(store.clone()),
+            HeaderMap::new(),
+            ApiPath(2),
+            query(cut_through),
+        )
+        .await
+        .unwrap();
+
+        let range = ApiQuery(serde_json::from_str(r#"{"from": 1, "to": 2}"#).unwrap());
+        let result = handler::get_transactions_range(State(store), range, query(cut_through)).await;
+        assert!(matches!(result, Err(Error::Syncing)));
+    }
+
+    #[tokio::test]
+    async fn test_range_ends_at_synced_height() {
+        let store = memory_store().await;
+        for height in 1..=3 {
+            store
+                .add_block(Block {
+                    height,
+                    hash: height.to_string(),
+                    transactions: vec![],
+                    spent: vec![],
+                    filters: vec![],
+                })
+                .await
+                .unwrap();
         }
+        let range = |range: &str| ApiQuery(serde_json::from_str(range).unwrap());
+        let filter = || ApiQuery(serde_json::from_str::<FilterQuery>("{}").unwrap());
+
+        let page = handler::get_scalars_range(
+            State(store.clone()),
+            range(r#"{"from": 2, "to": 10}"#),
+            filter(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.blocks.len(), 2);
+        assert_eq!(page.next, Some(4));
+
+        // Not indexed yet while syncing.
+        let result = handler::get_transactions_range(
+            State(store.clone()),
+            range(r#"{"from": 4, "to": 10}"#),
+            filter(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Syncing)));
+
+        // Not mined yet once synced.
+        store.set_synced();
+        let page = handler::get_transactions_range(
+            State(store.clone()),
+            range(r#"{"from": 4, "to": 10}"#),
+            filter(),
+        )
+        .await
+        .unwrap();
+        assert!(page.blocks.is_empty());
+        assert_eq!(page.next, Some(4));
+
+        let page = handler::get_transactions_range(
+            State(store),
+            range(r#"{"from": 1, "to": 3}"#),
+            filter(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.blocks.len(), 3);
+        assert_eq!(page.next, None);
+    }
+
+    #[tokio::test]
+    async fn test_content_negotiation() {
+        let store = memory_store().await;
+        store
+            .add_block(Block {
+                height: 1,
+                hash: "0000000000000000000320283a032748cef8227873ff4872689bf23f1cda83a5".into(),
+                transactions: vec![],
+                spent: vec![],
+                filters: vec![],
+            })
+            .await
+            .unwrap();
+        let no_filter = || ApiQuery(serde_json::from_str::<FilterQuery>("{}").unwrap());
+
+        let mut headers = HeaderMap::new();
+        let response =
+            handler::get_latest_scalars(State(store.clone()), headers.clone(), no_filter())
+                .await
+                .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[header::VARY], "accept");
+
+        // The binary format is excluded with q=0 or preferred less than JSON.
+        for accept in [
+            "application/octet-stream;q=0",
+            "application/octet-stream; q=0.0, */*",
+            "application/octet-stream;q=0.4, application/*;q=0.5",
+            "*/*",
+        ] {
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+            let response =
+                handler::get_latest_scalars(State(store.clone()), headers.clone(), no_filter())
+                    .await
+                    .unwrap();
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/json",
+                "{accept}"
+            );
+        }
+
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/json;q=0.5, application/octet-stream"),
+        );
+        let response = handler::get_latest_scalars(State(store), headers, no_filter())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], wire::CONTENT_TYPE);
+        assert_eq!(response.headers()[header::VARY], "accept");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let scalars = wire::decode_scalars(&body).unwrap();
+        assert_eq!(scalars.height, 1);
+        assert!(scalars.scalars.is_empty());
+    }
+
+    fn register_body(value: serde_json::Value) -> ApiJson<RegisterWallet> {
+        ApiJson(serde_json::from_value(value).unwrap())
+    }
+
+    fn limits() -> State<WalletLimits> {
+        State(WalletLimits::new(0, 10, 10))
+    }
+
+    #[tokio::test]
+    async fn test_register_wallet() {
+        let store = memory_store().await;
+        let secp = Secp256k1::new();
+        let scan_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let spend_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let body = serde_json::json!({
+            "scan_key": hex::encode(scan_key.secret_bytes()),
+            "spend_key": spend_key.public_key(&secp).to_string(),
+            "birthday": 840000,
+            "labels": [3, 1, 3],
+        });
+
+        let id =
+            handler::register_wallet(State(store.clone()), limits(), register_body(body.clone()))
+                .await
+                .unwrap()
+                .0
+                .id;
+        let wallet = store.get_wallet(id.clone()).await.unwrap().unwrap();
+        assert_eq!(wallet.birthday, 840000);
+        assert_eq!(wallet.labels, [1, 3]);
+
+        // Registering again keeps the id.
+        let mut again = body.clone();
+        again["birthday"] = 840001.into();
+        let again_id =
+            handler::register_wallet(State(store.clone()), limits(), register_body(again))
+                .await
+                .unwrap()
+                .0
+                .id;
+        assert_eq!(again_id, id);
+
+        // Watch-only: the spend secret key is neither accepted as spend key nor as extra field.
+        let mut spend_secret = body.clone();
+        spend_secret["spend_key"] = hex::encode(spend_key.secret_bytes()).into();
+        let err =
+            handler::register_wallet(State(store.clone()), limits(), register_body(spend_secret))
+                .await
+                .unwrap_err();
+        assert!(matches!(err, Error::BadRequest(_)));
+        let mut extra_field = body.clone();
+        extra_field["spend_secret_key"] = hex::encode(spend_key.secret_bytes()).into();
+        assert!(serde_json::from_value::<RegisterWallet>(extra_field).is_err());
+
+        let mut invalid_scan_key = body.clone();
+        invalid_scan_key["scan_key"] = "00".repeat(32).into();
+        let err = handler::register_wallet(
+            State(store.clone()),
+            limits(),
+            register_body(invalid_scan_key),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::BadRequest(_)));
+
+        // Birthdays below the minimum and new wallets above the limit are refused, registered
+        // wallets can still be registered again.
+        let min_birthday = State(WalletLimits::new(840001, 10, 10));
+        let err = handler::register_wallet(
+            State(store.clone()),
+            min_birthday,
+            register_body(body.clone()),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::BadRequest(_)));
+        let max_wallets = || State(WalletLimits::new(0, 1, 10));
+        let registered = handler::register_wallet(
+            State(store.clone()),
+            max_wallets(),
+            register_body(body.clone()),
+        )
+        .await;
+        assert!(registered.is_ok());
+        let mut other = body;
+        other["scan_key"] = hex::encode([3; 32]).into();
+        let err = handler::register_wallet(State(store), max_wallets(), register_body(other))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::LimitReached(_)));
+    }
+
+    async fn next_message(
+        messages: &mut futures::channel::mpsc::UnboundedReceiver<Message>,
+    ) -> serde_json::Value {
+        let Some(Message::Text(msg)) = messages.next().await else {
+            panic!("expected a text message");
+        };
+        serde_json::from_str(&msg).unwrap()
+    }
+
+    fn wallet_socket(
+        store: &Store,
+        wallet: Wallet,
+    ) -> futures::channel::mpsc::UnboundedReceiver<Message> {
+        let (write, messages) = futures::channel::mpsc::unbounded::<Message>();
+        let read = futures::stream::pending::<core::result::Result<Message, axum::Error>>();
+        tokio::spawn(handler::ws_subscribe_wallet_socket(
+            State(store.clone()),
+            wallet,
+            write,
+            read,
+        ));
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_wallet_outputs() {
+        let store = memory_store().await;
+        tokio::spawn(WalletScanner::new(store.clone()).run());
+        let secp = Secp256k1::new();
+        let case = receiving_cases().remove(0);
+        let key_material = &case.given["key_material"];
+        let scan_key = key_material["scan_priv_key"].as_str().unwrap();
+        let spend_key = SecretKey::from_str(key_material["spend_priv_key"].as_str().unwrap())
+            .unwrap()
+            .public_key(&secp);
+        let body = serde_json::json!({
+            "scan_key": scan_key,
+            "spend_key": spend_key.to_string(),
+            "birthday": 2,
+        });
+
+        let transaction = Transaction {
+            txid: "aa".repeat(32),
+            scalar: case.expected["tweak"].as_str().unwrap().into(),
+            position: 0,
+            outputs: case.given["outputs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .enumerate()
+                .map(|(vout, key)| Output {
+                    vout: vout as i64,
+                    value: 1000,
+                    spk: format!("5120{}", key.as_str().unwrap()),
+                })
+                .collect(),
+        };
+        let block = |height: i64| Block {
+            height,
+            hash: format!("{height:064x}"),
+            transactions: vec![Transaction {
+                txid: format!("{height:064x}"),
+                ..transaction.clone()
+            }],
+            spent: vec![],
+            filters: vec![],
+        };
+
+        // Blocks indexed before the registration are scanned from the birthday on.
+        for height in [1, 2, 3] {
+            store.add_block(block(height)).await.unwrap();
+        }
+        let id =
+            handler::register_wallet(State(store.clone()), limits(), register_body(body.clone()))
+                .await
+                .unwrap()
+                .0
+                .id;
+        let wallet = store.get_wallet(id.clone()).await.unwrap().unwrap();
+        let mut messages = wallet_socket(&store, wallet.clone());
+
+        for height in [2, 3] {
+            let msg = next_message(&mut messages).await;
+            assert_eq!(msg["height"], height);
+            let expected = &case.expected["outputs"][0];
+            assert_eq!(msg["outputs"][0]["txid"], format!("{height:064x}"));
+            assert_eq!(msg["outputs"][0]["pub_key"], expected["pub_key"]);
+            assert_eq!(msg["outputs"][0]["tweak"], expected["priv_key_tweak"]);
+            assert!(msg["outputs"][0]["label"].is_null());
+        }
+
+        // New blocks are scanned as they are indexed, blocks of a new branch again.
+        store.add_block(block(4)).await.unwrap();
+        assert_eq!(next_message(&mut messages).await["height"], 4);
+        store.disconnect_blocks_above(3).await.unwrap();
+        assert_eq!(
+            next_message(&mut messages).await["disconnected"]["height"],
+            4
+        );
+        store.add_block(block(4)).await.unwrap();
+        assert_eq!(next_message(&mut messages).await["height"], 4);
+
+        // The scanned outputs are stored, new subscriptions get them without scanning again.
+        let scanned = store.get_wallet(id.clone()).await.unwrap().unwrap();
+        assert_eq!(scanned.scanned_height, Some(4));
+        let mut again = wallet_socket(&store, wallet);
+        for height in [2, 3, 4] {
+            assert_eq!(next_message(&mut again).await["height"], height);
+        }
+
+        // Registering again with another birthday drops the outputs, subscribers are closed.
+        let mut later_birthday = body;
+        later_birthday["birthday"] = 3.into();
+        let again_id = handler::register_wallet(
+            State(store.clone()),
+            limits(),
+            register_body(later_birthday),
+        )
+        .await
+        .unwrap()
+        .0
+        .id;
+        assert_eq!(again_id, id);
+        assert!(next_message(&mut messages).await["error"].is_string());
+        assert!(messages.next().await.is_none());
     }
 }
@@ -0,0 +1,260 @@
+// Compact binary encoding of block scalars and transactions, served instead of JSON if the client
+// sends `Accept: application/octet-stream`. Integers are little-endian, hashes and keys are the bytes
+// of the hex strings of the JSON form.
+//
+// Version 1 layout:
+//
+//   version       u8        1
+//   kind          u8        1 = scalars, 2 = transactions
+//   height        u32
+//   block hash    [u8; 32]
+//   count         u32       number of scalars or transactions
+//
+// followed by `count` scalars:
+//
+//   scalar        [u8; 33]  compressed public key
+//
+// or `count` transactions:
+//
+//   txid          [u8; 32]
+//   scalar        [u8; 33]
+//   output count  u32
+//   outputs       vout u32, value u64, x-only key [u8; 32] (spk without OP_1 0x20)
+
+use crate::store::model::{BlockScalars, BlockTransactions, Output, Transaction};
+use crate::{Error, P2TR_PREFIX, Result};
+
+pub const CONTENT_TYPE: &str = "application/octet-stream";
+pub const VERSION: u8 = 1;
+
+const KIND_SCALARS: u8 = 1;
+const KIND_TRANSACTIONS: u8 = 2;
+
+pub fn encode_scalars(block: &BlockScalars) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(42 + block.scalars.len() * 33);
+    write_header(&mut buf, KIND_SCALARS, block.height, &block.block_hash)?;
+    write_u32(&mut buf, block.scalars.len())?;
+    for scalar in block.scalars.iter() {
+        write_hex::<33>(&mut buf, scalar)?;
+    }
+    Ok(buf)
+}
+
+pub fn decode_scalars(bytes: &[u8]) -> Result<BlockScalars> {
+    let mut reader = Reader(bytes);
+    let (height, block_hash) = reader.header(KIND_SCALARS)?;
+    let count = reader.u32()?;
+    let scalars = (0..count)
+        .map(|_| reader.hex::<33>())
+        .collect::<Result<Vec<String>>>()?;
+    reader.finish()?;
+
+    Ok(BlockScalars {
+        height,
+        block_hash,
+        scalars,
+    })
+}
+
+pub fn encode_transactions(block: &BlockTransactions) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    write_header(&mut buf, KIND_TRANSACTIONS, block.height, &block.block_hash)?;
+    write_u32(&mut buf, block.transactions.len())?;
+    for tx in block.transactions.iter() {
+        write_hex::<32>(&mut buf, &tx.txid)?;
+        write_hex::<33>(&mut buf, &tx.scalar)?;
+        write_u32(&mut buf, tx.outputs.len())?;
+        for output in tx.outputs.iter() {
+            write_u32(&mut buf, output.vout)?;
+            let value = u64::try_from(output.value).map_err(|_| invalid("value"))?;
+            buf.extend_from_slice(&value.to_le_bytes());
+            let spk = hex::decode(&output.spk)?;
+            let key = spk
+                .strip_prefix(&P2TR_PREFIX)
+                .filter(|key| key.len() == 32)
+                .ok_or_else(|| invalid("taproot scriptPubKey"))?;
+            buf.extend_from_slice(key);
+        }
+    }
+    Ok(buf)
+}
+
+pub fn decode_transactions(bytes: &[u8]) -> Result<BlockTransactions> {
+    let mut reader = Reader(bytes);
+    let (height, block_hash) = reader.header(KIND_TRANSACTIONS)?;
+    let count = reader.u32()?;
+    let mut transactions = vec![];
+    for _ in 0..count {
+        let txid = reader.hex::<32>()?;
+        let scalar = reader.hex::<33>()?;
+        let output_count = reader.u32()?;
+        let mut outputs = vec![];
+        for _ in 0..output_count {
+            let vout = reader.u32()?;
+            let value =
+                i64::try_from(u64::from_le_bytes(reader.bytes()?)).map_err(|_| invalid("value"))?;
+            let key: [u8; 32] = reader.bytes()?;
+            outputs.push(Output {
+                vout,
+                value,
+                spk: hex::encode([P2TR_PREFIX.as_slice(), &key].concat()),
+            });
+        }
+        transactions.push(Transaction {
+            txid,
+            scalar,
+            position: 0,
+            outputs,
+        });
+    }
+    reader.finish()?;
+
+    Ok(BlockTransactions {
+        height,
+        block_hash,
+        transactions,
+    })
+}
+
+fn invalid(what: &str) -> Error {
+    Error::Decode(format!("invalid {what}"))
+}
+
+fn write_header(buf: &mut Vec<u8>, kind: u8, height: i64, block_hash: &str) -> Result<()> {
+    buf.push(VERSION);
+    buf.push(kind);
+    write_u32(buf, height)?;
+    write_hex::<32>(buf, block_hash)
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: impl TryInto<u32>) -> Result<()> {
+    let value: u32 = value.try_into().map_err(|_| invalid("u32"))?;
+    buf.extend_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+fn write_hex<const N: usize>(buf: &mut Vec<u8>, hex: &str) -> Result<()> {
+    let bytes: [u8; N] = hex::decode(hex)?
+        .try_into()
+        .map_err(|_| invalid("hex length"))?;
+    buf.extend_from_slice(&bytes);
+    Ok(())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let (bytes, rest) = self
+            .0
+            .split_first_chunk::<N>()
+            .ok_or_else(|| Error::Decode("unexpected end of data".into()))?;
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn u32(&mut self) -> Result<i64> {
+        Ok(u32::from_le_bytes(self.bytes()?).into())
+    }
+
+    fn hex<const N: usize>(&mut self) -> Result<String> {
+        Ok(hex::encode(self.bytes::<N>()?))
+    }
+
+    fn header(&mut self, kind: u8) -> Result<(i64, String)> {
+        let [version, actual_kind] = self.bytes()?;
+        if version != VERSION {
+            return Err(Error::Decode(format!("unsupported version {version}")));
+        }
+        if actual_kind != kind {
+            return Err(Error::Decode(format!("unexpected kind {actual_kind}")));
+        }
+        Ok((self.u32()?, self.hex::<32>()?))
+    }
+
+    fn finish(&self) -> Result<()> {
+        if !self.0.is_empty() {
+            return Err(Error::Decode(format!("{} trailing bytes", self.0.len())));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_HASH: &str = "0000000000000000000320283a032748cef8227873ff4872689bf23f1cda83a5";
+
+    fn transactions() -> BlockTransactions {
+        BlockTransactions {
+            height: 840000,
+            block_hash: BLOCK_HASH.into(),
+            transactions: vec![Transaction {
+                txid: "370818bea6e50a63d628d6fa179411237be5a45419a2c36867926e50b48ca848".into(),
+                scalar: "035c2fb8ce078f77db70beb7317dede4cd079a83fc231c3c34d222faa306e7c48c".into(),
+                position: 0,
+                outputs: vec![
+                    Output {
+                        vout: 0,
+                        value: 988438,
+                        spk: "5120ae66becf5234528a3f9d3e64545066a42f55c625daf288827c96fc5757c10c2b"
+                            .into(),
+                    },
+                    Output {
+                        vout: 1,
+                        value: 100000000,
+                        spk: "5120cc685d57c383b48ec9bbce71668ecda8c90aa57c5012347557484dfbcfff8981"
+                            .into(),
+                    },
+                ],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_scalars_round_trip() {
+        let block = BlockScalars {
+            height: 840000,
+            block_hash: BLOCK_HASH.into(),
+            scalars: vec![
+                "0300260cd166b0b9375963fdeea829c638ad74e69ddba80a43bf3388619d2ee96d".into(),
+                "02393c02d8fce020e37e709367a74835bc2f4a292307be15d34211fe6982494caf".into(),
+            ],
+        };
+        let encoded = encode_scalars(&block).unwrap();
+        assert_eq!(encoded.len(), 42 + 2 * 33);
+        assert_eq!(encoded[..2], [VERSION, KIND_SCALARS]);
+
+        let decoded = decode_scalars(&encoded).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&block).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transactions_round_trip() {
+        let block = transactions();
+        let encoded = encode_transactions(&block).unwrap();
+        assert_eq!(encoded.len(), 42 + 32 + 33 + 4 + 2 * 44);
+
+        let decoded = decode_transactions(&encoded).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&block).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let encoded = encode_transactions(&transactions()).unwrap();
+        assert!(decode_transactions(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_transactions(&[encoded.as_slice(), &[0]].concat()).is_err());
+        assert!(decode_scalars(&encoded).is_err());
+
+        let mut future_version = encoded.clone();
+        future_version[0] = VERSION + 1;
+        assert!(decode_transactions(&future_version).is_err());
+    }
+}
@@ -1,31 +1,36 @@
 use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use model::Output;
 use model::{
-    Block, JoinedTransactionOutput, JoinedTransactionOutputCollection, Scalar, Scalars,
-    Transaction, Transactions,
+    Block, BlockEvent, BlockFilter, BlockScalars, BlockTransactions, FilterKind,
+    JoinedTransactionOutput, JoinedTransactionOutputCollection, MempoolEvent, OutputStatus, Scalar,
+    Spend, SpentOutpoint, Transaction, TransactionFilter, Transactions, Wallet,
 };
+use model::{BlockOutputs, DisconnectedBlock, WalletEvent};
 use sqlx::sqlite::SqliteQueryResult;
-use sqlx::{Sqlite, SqlitePool, sqlite::SqliteConnectOptions};
-use tokio::sync::broadcast;
+use sqlx::{Executor, Sqlite, SqlitePool, sqlite::SqliteConnectOptions};
+use tokio::sync::{Mutex, broadcast};
 use tracing::debug;
 
-use crate::Result;
 use crate::config::DatabaseConfig;
+use crate::scan::OwnedOutput;
+use crate::{Error, Result};
 
 pub mod model;
 
-// FIXME: Right now in case the store is queried with height or txid it might return an empty vec.
-// However an empty vec could mean the height/txid exists but there is no txs/scalars for example,
-// or that the provided height/txid was wrong. In the latter case I'd like to return 404 not found
-// instead of an empty vec...but using fetch_all with the single query style I use right now, I
-// don't know if the parameter was wrong, or there's just no data.. Need to solve this elegantly..
-// for now I'll just return empty vecs..
-
 #[derive(Clone)]
 pub struct Store {
     pool: SqlitePool,
-    sub_tx: broadcast::Sender<Block>,
+    sub_tx: broadcast::Sender<BlockEvent>,
+    mempool_sub_tx: broadcast::Sender<MempoolEvent>,
+    wallet_sub_tx: broadcast::Sender<WalletEvent>,
+    // Held while wallet outputs change and their events are sent, and while wallet subscribers read
+    // the stored outputs, so each change is either in the stored outputs or sent as event.
+    wallet_lock: Arc<Mutex<()>>,
+    // Whether the syncer reached the node's tip once.
+    synced: Arc<AtomicBool>,
 }
 
 impl Store {
@@ -37,8 +42,17 @@ impl Store {
         sqlx::migrate!("./migrations/").run(&pool).await?;
 
         let (sub_tx, _) = broadcast::channel(512);
+        let (mempool_sub_tx, _) = broadcast::channel(512);
+        let (wallet_sub_tx, _) = broadcast::channel(512);
 
-        Ok(Self { pool, sub_tx })
+        Ok(Self {
+            pool,
+            sub_tx,
+            mempool_sub_tx,
+            wallet_sub_tx,
+            wallet_lock: Arc::new(Mutex::new(())),
+            synced: Arc::new(AtomicBool::new(false)),
+        })
     }
 
     async fn insert_output<'a>(
@@ -65,13 +79,16 @@ impl Store {
         transaction: &Transaction,
         block_height: i64,
     ) -> Result<SqliteQueryResult> {
+        let max_value = transaction.max_output_value();
         let query_result = sqlx::query_scalar!(
             r#"
-        INSERT INTO transactions (id, block, txid, scalar) VALUES (NULL, ?, ?, ?)
+        INSERT INTO transactions (id, block, txid, scalar, max_value, position) VALUES (NULL, ?, ?, ?, ?, ?)
             "#,
             block_height,
             transaction.txid,
             transaction.scalar,
+            max_value,
+            transaction.position,
         )
         .execute(&mut **db_tx)
         .await?;
@@ -97,6 +114,48 @@ impl Store {
         Ok(())
     }
 
+    async fn mark_spent<'a>(
+        db_tx: &mut sqlx::Transaction<'a, Sqlite>,
+        spent: &[SpentOutpoint],
+        block_height: i64,
+    ) -> Result<()> {
+        for outpoint in spent.iter() {
+            sqlx::query!(
+                r#"
+        UPDATE outputs SET spent_txid = ?, spent_height = ?
+        WHERE vout = ? AND tx IN (SELECT id FROM transactions WHERE txid = ?)
+        "#,
+                outpoint.spending_txid,
+                block_height,
+                outpoint.vout,
+                outpoint.txid,
+            )
+            .execute(&mut **db_tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn insert_filters<'a>(
+        db_tx: &mut sqlx::Transaction<'a, Sqlite>,
+        block: &Block,
+    ) -> Result<()> {
+        for filter in block.filters.iter() {
+            let kind = filter.kind.as_str();
+            sqlx::query!(
+                "INSERT OR REPLACE INTO filters (block, kind, data) VALUES (?, ?, ?)",
+                block.height,
+                kind,
+                filter.data,
+            )
+            .execute(&mut **db_tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
     async fn insert_block(
         db_tx: &mut sqlx::Transaction<'static, Sqlite>,
         block: &Block,
@@ -113,79 +172,390 @@ impl Store {
         Ok(query_result)
     }
 
-    pub fn subscribe_blocks(&self) -> broadcast::Receiver<Block> {
-        self.sub_tx.subscribe()
-    }
-
-    pub async fn get_latest_scalars(&self) -> Result<Scalars> {
-        let scalars = sqlx::query_scalar!(
-            "SELECT scalar FROM transactions WHERE block = (SELECT MAX(height) FROM blocks)"
+    async fn delete_blocks_above<'a>(
+        db_tx: &mut sqlx::Transaction<'a, Sqlite>,
+        height: i64,
+    ) -> Result<()> {
+        // Outputs spent in the disconnected blocks are unspent again.
+        sqlx::query!(
+            "UPDATE outputs SET spent_txid = NULL, spent_height = NULL WHERE spent_height > ?",
+            height
         )
-        .fetch_all(&self.pool)
+        .execute(&mut **db_tx)
         .await?;
-        Ok(Scalars { scalars })
+        sqlx::query!(
+            "DELETE FROM outputs WHERE tx IN (SELECT id FROM transactions WHERE block > ?)",
+            height
+        )
+        .execute(&mut **db_tx)
+        .await?;
+        sqlx::query!("DELETE FROM filters WHERE block > ?", height)
+            .execute(&mut **db_tx)
+            .await?;
+        sqlx::query!("DELETE FROM transactions WHERE block > ?", height)
+            .execute(&mut **db_tx)
+            .await?;
+        sqlx::query!("DELETE FROM blocks WHERE height > ?", height)
+            .execute(&mut **db_tx)
+            .await?;
+        // Wallets continue scanning from the new tip.
+        sqlx::query!("DELETE FROM wallet_outputs WHERE block > ?", height)
+            .execute(&mut **db_tx)
+            .await?;
+        sqlx::query!(
+            r#"
+        UPDATE wallets SET
+            scanned_height = (SELECT MAX(height) FROM blocks),
+            scanned_hash = (SELECT hash FROM blocks ORDER BY height DESC LIMIT 1)
+        WHERE scanned_height > ?
+        "#,
+            height
+        )
+        .execute(&mut **db_tx)
+        .await?;
+        // Blocks connected above the fork point are indexed with their spends.
+        let next_height = height + 1;
+        sqlx::query!("UPDATE backfill SET height = MIN(height, ?)", next_height)
+            .execute(&mut **db_tx)
+            .await?;
+        Ok(())
     }
 
-    pub async fn get_scalars_by_height(&self, height: i64) -> Result<Scalars> {
-        let scalars =
-            sqlx::query_scalar!("SELECT scalar FROM transactions WHERE block = ?", height)
-                .fetch_all(&self.pool)
-                .await?;
-
-        Ok(Scalars { scalars })
+    async fn select_block_hash<'e, E>(executor: E, height: i64) -> Result<Option<String>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let hash = sqlx::query_scalar!("SELECT hash FROM blocks WHERE height = ?", height)
+            .fetch_optional(executor)
+            .await?;
+        Ok(hash)
     }
 
-    pub async fn get_scalar_by_txid(&self, txid: String) -> Result<Option<Scalar>> {
-        let scalar = sqlx::query_scalar!("SELECT scalar FROM transactions WHERE txid = ?", txid)
-            .fetch_optional(&self.pool)
+    // Height and hash of the highest synced block.
+    async fn select_latest_block<'e, E>(executor: E) -> Result<Option<(i64, String)>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let record = sqlx::query!("SELECT height, hash FROM blocks ORDER BY height DESC LIMIT 1")
+            .fetch_optional(executor)
             .await?;
+        Ok(record.map(|record| (record.height, record.hash)))
+    }
 
-        Ok(scalar.map(|scalar| Scalar { scalar }))
+    async fn select_scalars_by_height<'e, E>(
+        executor: E,
+        height: i64,
+        filter: TransactionFilter,
+    ) -> Result<Vec<String>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let scalars = sqlx::query_scalar!(
+            r#"
+        SELECT t.scalar FROM transactions t
+        WHERE t.block = ? AND t.max_value >= ?
+        AND (? = 0 OR EXISTS (SELECT 1 FROM outputs u WHERE u.tx = t.id AND u.spent_txid IS NULL))
+        ORDER BY t.position
+        "#,
+            height,
+            filter.dust_limit,
+            filter.cut_through
+        )
+        .fetch_all(executor)
+        .await?;
+        Ok(scalars)
     }
 
-    pub async fn get_latest_transactions(&self) -> Result<Transactions> {
+    async fn select_transactions_by_height<'e, E>(
+        executor: E,
+        height: i64,
+        filter: TransactionFilter,
+    ) -> Result<Transactions>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
         let collection: JoinedTransactionOutputCollection = sqlx::query_as!(
             JoinedTransactionOutput,
             r#"
         SELECT 
             t.txid, 
             t.scalar, 
+            t.position, 
             o.vout, 
             o.value, 
             o.script_pub_key 
         FROM transactions t
         INNER JOIN outputs o ON t.id = o.tx
-        WHERE t.block = (SELECT MAX(height) FROM blocks)
+        WHERE t.block = ? AND t.max_value >= ?
+        AND (? = 0 OR EXISTS (SELECT 1 FROM outputs u WHERE u.tx = t.id AND u.spent_txid IS NULL))
+        ORDER BY t.position, o.vout
         "#,
+            height,
+            filter.dust_limit,
+            filter.cut_through
         )
-        .fetch_all(&self.pool)
+        .fetch_all(executor)
         .await?
         .into();
 
         Ok(collection.into())
     }
 
-    pub async fn get_transactions_by_height(&self, height: i64) -> Result<Transactions> {
-        let collection: JoinedTransactionOutputCollection = sqlx::query_as!(
-            JoinedTransactionOutput,
+    pub fn set_synced(&self) {
+        self.synced.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribe_blocks(&self) -> broadcast::Receiver<BlockEvent> {
+        self.sub_tx.subscribe()
+    }
+
+    pub fn subscribe_mempool(&self) -> broadcast::Receiver<MempoolEvent> {
+        self.mempool_sub_tx.subscribe()
+    }
+
+    pub async fn get_latest_scalars(
+        &self,
+        filter: TransactionFilter,
+    ) -> Result<Option<BlockScalars>> {
+        let mut db_tx = self.pool.begin().await?;
+        let Some((height, block_hash)) = Store::select_latest_block(&mut *db_tx).await? else {
+            return Ok(None);
+        };
+        let scalars = Store::select_scalars_by_height(&mut *db_tx, height, filter).await?;
+        db_tx.commit().await?;
+
+        Ok(Some(BlockScalars {
+            height,
+            block_hash,
+            scalars,
+        }))
+    }
+
+    // Returns `None` if the block at `height` is not synced (yet).
+    pub async fn get_scalars_by_height(
+        &self,
+        height: i64,
+        filter: TransactionFilter,
+    ) -> Result<Option<BlockScalars>> {
+        let mut db_tx = self.pool.begin().await?;
+        let Some(block_hash) = Store::select_block_hash(&mut *db_tx, height).await? else {
+            return Ok(None);
+        };
+        let scalars = Store::select_scalars_by_height(&mut *db_tx, height, filter).await?;
+        db_tx.commit().await?;
+
+        Ok(Some(BlockScalars {
+            height,
+            block_hash,
+            scalars,
+        }))
+    }
+
+    // Scalars of all indexed blocks from `from` to `to` (inclusive) grouped by height, including
+    // blocks without scalars.
+    pub async fn get_scalars_by_height_range(
+        &self,
+        from: i64,
+        to: i64,
+        filter: TransactionFilter,
+    ) -> Result<Vec<BlockScalars>> {
+        let records = sqlx::query!(
+            r#"
+        SELECT b.height AS "height!", b.hash, t.scalar AS "scalar?" FROM blocks b
+        LEFT JOIN transactions t ON t.block = b.height AND t.max_value >= ?
+        AND (? = 0 OR EXISTS (SELECT 1 FROM outputs u WHERE u.tx = t.id AND u.spent_txid IS NULL))
+        WHERE b.height BETWEEN ? AND ?
+        ORDER BY b.height, t.position
+        "#,
+            filter.dust_limit,
+            filter.cut_through,
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut blocks: Vec<BlockScalars> = vec![];
+        for record in records {
+            if blocks
+                .last()
+                .is_none_or(|block| block.height != record.height)
+            {
+                blocks.push(BlockScalars {
+                    height: record.height,
+                    block_hash: record.hash,
+                    scalars: vec![],
+                });
+            }
+            if let (Some(block), Some(scalar)) = (blocks.last_mut(), record.scalar) {
+                block.scalars.push(scalar);
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    pub async fn get_scalar_by_txid(&self, txid: String) -> Result<Option<Scalar>> {
+        let scalar = sqlx::query_scalar!("SELECT scalar FROM transactions WHERE txid = ?", txid)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(scalar.map(|scalar| Scalar { scalar }))
+    }
+
+    pub async fn get_latest_transactions(
+        &self,
+        filter: TransactionFilter,
+    ) -> Result<Option<BlockTransactions>> {
+        let mut db_tx = self.pool.begin().await?;
+        let Some((height, block_hash)) = Store::select_latest_block(&mut *db_tx).await? else {
+            return Ok(None);
+        };
+        let transactions =
+            Store::select_transactions_by_height(&mut *db_tx, height, filter).await?;
+        db_tx.commit().await?;
+
+        Ok(Some(BlockTransactions {
+            height,
+            block_hash,
+            transactions: transactions.transactions,
+        }))
+    }
+
+    // Returns `None` if the block at `height` is not synced (yet).
+    pub async fn get_transactions_by_height(
+        &self,
+        height: i64,
+        filter: TransactionFilter,
+    ) -> Result<Option<BlockTransactions>> {
+        let mut db_tx = self.pool.begin().await?;
+        let Some(block_hash) = Store::select_block_hash(&mut *db_tx, height).await? else {
+            return Ok(None);
+        };
+        let transactions =
+            Store::select_transactions_by_height(&mut *db_tx, height, filter).await?;
+        db_tx.commit().await?;
+
+        Ok(Some(BlockTransactions {
+            height,
+            block_hash,
+            transactions: transactions.transactions,
+        }))
+    }
+
+    // First height from `from` to `to` at which the blocks' transaction count adds up to more than
+    // `max_transactions`, if any.
+    pub async fn get_height_exceeding_transactions(
+        &self,
+        from: i64,
+        to: i64,
+        max_transactions: i64,
+    ) -> Result<Option<i64>> {
+        let height = sqlx::query_scalar!(
+            r#"
+        SELECT height AS "height!" FROM (
+            SELECT height, SUM(tx_count) OVER (ORDER BY height) AS total
+            FROM blocks WHERE height BETWEEN ? AND ?
+        )
+        WHERE total > ?
+        ORDER BY height LIMIT 1
+        "#,
+            from,
+            to,
+            max_transactions
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(height)
+    }
+
+    // Transactions of all indexed blocks from `from` to `to` (inclusive) grouped by height, including
+    // blocks without transactions.
+    pub async fn get_transactions_by_height_range(
+        &self,
+        from: i64,
+        to: i64,
+        filter: TransactionFilter,
+    ) -> Result<Vec<BlockTransactions>> {
+        let records = sqlx::query!(
             r#"
         SELECT 
-            t.txid, 
-            t.scalar, 
-            o.vout, 
-            o.value, 
-            o.script_pub_key 
-        FROM transactions t
-        INNER JOIN outputs o ON t.id = o.tx
-        WHERE t.block = ? 
+            b.height AS "height!", 
+            b.hash, 
+            t.txid AS "txid?", 
+            t.scalar AS "scalar?", 
+            t.position AS "position?", 
+            o.vout AS "vout?", 
+            o.value AS "value?", 
+            o.script_pub_key AS "script_pub_key?" 
+        FROM blocks b
+        LEFT JOIN transactions t ON t.block = b.height AND t.max_value >= ?
+        AND (? = 0 OR EXISTS (SELECT 1 FROM outputs u WHERE u.tx = t.id AND u.spent_txid IS NULL))
+        LEFT JOIN outputs o ON t.id = o.tx
+        WHERE b.height BETWEEN ? AND ?
+        ORDER BY b.height, t.position, o.vout
         "#,
-            height
+            filter.dust_limit,
+            filter.cut_through,
+            from,
+            to
         )
         .fetch_all(&self.pool)
-        .await?
-        .into();
+        .await?;
 
-        Ok(collection.into())
+        let mut blocks: Vec<(i64, String, Vec<JoinedTransactionOutput>)> = vec![];
+        for record in records {
+            if blocks
+                .last()
+                .is_none_or(|(height, _, _)| *height != record.height)
+            {
+                blocks.push((record.height, record.hash, vec![]));
+            }
+            let output = match (
+                record.txid,
+                record.scalar,
+                record.position,
+                record.vout,
+                record.value,
+                record.script_pub_key,
+            ) {
+                (
+                    Some(txid),
+                    Some(scalar),
+                    Some(position),
+                    Some(vout),
+                    Some(value),
+                    Some(script_pub_key),
+                ) => JoinedTransactionOutput {
+                    txid,
+                    scalar,
+                    position,
+                    vout,
+                    value,
+                    script_pub_key,
+                },
+                _ => continue,
+            };
+            if let Some((_, _, outputs)) = blocks.last_mut() {
+                outputs.push(output);
+            }
+        }
+
+        Ok(blocks
+            .into_iter()
+            .map(|(height, block_hash, outputs)| {
+                let transactions: Transactions = JoinedTransactionOutputCollection(outputs).into();
+                BlockTransactions {
+                    height,
+                    block_hash,
+                    transactions: transactions.transactions,
+                }
+            })
+            .collect())
     }
 
     // Returns Vec aswell because we use join to get the outputs. This means one transaction with
@@ -197,12 +567,14 @@ impl Store {
         SELECT 
             t.txid, 
             t.scalar, 
+            t.position, 
             o.vout, 
             o.value, 
             o.script_pub_key 
         FROM transactions t
         INNER JOIN outputs o ON t.id = o.tx
         WHERE t.txid = ? 
+        ORDER BY o.vout
         "#,
             txid
         )
@@ -213,6 +585,59 @@ impl Store {
         Ok(collection.into())
     }
 
+    pub async fn get_output(&self, txid: String, vout: i64) -> Result<Option<OutputStatus>> {
+        let record = sqlx::query!(
+            r#"
+        SELECT 
+            o.value, 
+            o.script_pub_key, 
+            o.spent_txid, 
+            o.spent_height,
+            t.block
+        FROM outputs o
+        INNER JOIN transactions t ON t.id = o.tx
+        WHERE t.txid = ? AND o.vout = ?
+        "#,
+            txid,
+            vout
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(record.map(|record| OutputStatus {
+            txid,
+            vout,
+            height: record.block,
+            value: record.value,
+            spk: record.script_pub_key,
+            spent: record
+                .spent_txid
+                .zip(record.spent_height)
+                .map(|(txid, height)| Spend { txid, height }),
+        }))
+    }
+
+    pub async fn get_filter(&self, height: i64, kind: FilterKind) -> Result<Option<BlockFilter>> {
+        let kind = kind.as_str();
+        let record = sqlx::query!(
+            r#"
+        SELECT b.hash, f.data FROM filters f
+        INNER JOIN blocks b ON b.height = f.block
+        WHERE f.block = ? AND f.kind = ?
+        "#,
+            height,
+            kind
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(record.map(|record| BlockFilter {
+            height,
+            block_hash: record.hash,
+            filter: record.data,
+        }))
+    }
+
     pub async fn get_synced_blocks_height(&self) -> Result<Option<i64>> {
         let height = sqlx::query_scalar!("SELECT MAX(height) FROM blocks")
             .fetch_one(&self.pool)
@@ -220,23 +645,984 @@ impl Store {
         Ok(height)
     }
 
+    pub async fn get_block_hash(&self, height: i64) -> Result<Option<String>> {
+        Store::select_block_hash(&self.pool, height).await
+    }
+
+    pub async fn get_first_block_height(&self) -> Result<Option<i64>> {
+        let height = sqlx::query_scalar!("SELECT MIN(height) FROM blocks")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(height)
+    }
+
+    // Height from which on spends are recorded for all outputs, see `backfill_block`.
+    pub async fn get_backfill_height(&self) -> Result<i64> {
+        let height = sqlx::query_scalar!("SELECT height FROM backfill")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(height)
+    }
+
+    // Record the spends and filters of a block that was indexed before they were recorded and lower
+    // the backfill height to it. Returns false without changes if the stored block has another hash.
+    pub async fn backfill_block(&self, block: &Block) -> Result<bool> {
+        let mut db_tx = self.pool.begin().await?;
+
+        let stored_hash = Store::select_block_hash(&mut *db_tx, block.height).await?;
+        if stored_hash.as_ref() != Some(&block.hash) {
+            return Ok(false);
+        }
+        Store::mark_spent(&mut db_tx, &block.spent, block.height).await?;
+        Store::insert_filters(&mut db_tx, block).await?;
+        sqlx::query!("UPDATE backfill SET height = MIN(height, ?)", block.height)
+            .execute(&mut *db_tx)
+            .await?;
+
+        db_tx.commit().await?;
+
+        Ok(true)
+    }
+
     pub async fn add_block(&self, block: Block) -> Result<()> {
         let mut db_tx = self.pool.begin().await?;
 
         Store::insert_block(&mut db_tx, &block).await?;
         Store::insert_transactions(&mut db_tx, &block.transactions, block.height).await?;
+        Store::mark_spent(&mut db_tx, &block.spent, block.height).await?;
+        Store::insert_filters(&mut db_tx, &block).await?;
 
         db_tx.commit().await?;
 
-        self.notify_subscribers(block);
+        self.notify_subscribers(BlockEvent::Connected(block));
 
         Ok(())
     }
 
-    fn notify_subscribers(&self, block: Block) {
-        match self.sub_tx.send(block) {
-            Ok(num_sub) => debug!("Notified {} subscribers of new block.", num_sub),
-            Err(_) => debug!("There are no subscribers for new blocks."),
+    // Removes all blocks above `height` (the fork point of a reorg) with their transactions, outputs
+    // and the wallet outputs found in them in one DB transaction. Subscribers are notified of each
+    // disconnected block, starting with the old tip. Returns the number of disconnected blocks.
+    pub async fn disconnect_blocks_above(&self, height: i64) -> Result<usize> {
+        let _wallet_lock = self.wallet_lock.lock().await;
+        let mut db_tx = self.pool.begin().await?;
+
+        let records = sqlx::query!(
+            "SELECT height, hash FROM blocks WHERE height > ? ORDER BY height DESC",
+            height
+        )
+        .fetch_all(&mut *db_tx)
+        .await?;
+
+        let mut disconnected = vec![];
+        for record in records {
+            let transactions = Store::select_transactions_by_height(
+                &mut *db_tx,
+                record.height,
+                TransactionFilter::default(),
+            )
+            .await?;
+            disconnected.push(Block {
+                height: record.height,
+                hash: record.hash,
+                transactions: transactions.transactions,
+                spent: vec![],
+                filters: vec![],
+            });
+        }
+
+        Store::delete_blocks_above(&mut db_tx, height).await?;
+
+        db_tx.commit().await?;
+
+        let count = disconnected.len();
+        for block in disconnected {
+            self.notify_wallet_subscribers(WalletEvent::Disconnected(DisconnectedBlock {
+                height: block.height,
+                hash: block.hash.clone(),
+            }));
+            self.notify_subscribers(BlockEvent::Disconnected(block));
         }
+
+        Ok(count)
+    }
+
+    // Registering a wallet again updates its birthday and labels. If they changed, its outputs are
+    // dropped and its blocks are scanned again.
+    pub async fn add_wallet(&self, wallet: &Wallet) -> Result<()> {
+        let labels = serde_json::to_string(&wallet.labels).expect("labels serialize to JSON");
+        let _wallet_lock = self.wallet_lock.lock().await;
+        let mut db_tx = self.pool.begin().await?;
+
+        let registered = sqlx::query!(
+            "SELECT birthday, labels FROM wallets WHERE id = ?",
+            wallet.id
+        )
+        .fetch_optional(&mut *db_tx)
+        .await?;
+        if registered
+            .as_ref()
+            .is_some_and(|record| record.birthday == wallet.birthday && record.labels == labels)
+        {
+            return Ok(());
+        }
+
+        sqlx::query!(
+            r#"
+        INSERT INTO wallets (id, scan_key, spend_key, birthday, labels) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT (id) DO UPDATE SET
+            birthday = excluded.birthday,
+            labels = excluded.labels,
+            scanned_height = NULL,
+            scanned_hash = NULL
+        "#,
+            wallet.id,
+            wallet.scan_key,
+            wallet.spend_key,
+            wallet.birthday,
+            labels,
+        )
+        .execute(&mut *db_tx)
+        .await?;
+        sqlx::query!("DELETE FROM wallet_outputs WHERE wallet = ?", wallet.id)
+            .execute(&mut *db_tx)
+            .await?;
+
+        db_tx.commit().await?;
+
+        self.notify_wallet_subscribers(WalletEvent::Registered(wallet.id.clone()));
+
+        Ok(())
+    }
+
+    pub async fn get_wallet(&self, id: String) -> Result<Option<Wallet>> {
+        let record = sqlx::query!(
+            r#"
+        SELECT id, scan_key, spend_key, birthday, labels, scanned_height
+        FROM wallets WHERE id = ?
+        "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        record
+            .map(|record| {
+                let labels = serde_json::from_str(&record.labels)
+                    .map_err(|err| Error::Decode(format!("invalid wallet labels: {err}")))?;
+                Ok(Wallet {
+                    id: record.id,
+                    scan_key: record.scan_key,
+                    spend_key: record.spend_key,
+                    birthday: record.birthday,
+                    labels,
+                    scanned_height: record.scanned_height,
+                })
+            })
+            .transpose()
+    }
+
+    pub async fn get_wallet_ids(&self) -> Result<Vec<String>> {
+        let ids = sqlx::query_scalar!("SELECT id FROM wallets")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(ids)
+    }
+
+    pub async fn count_wallets(&self) -> Result<i64> {
+        let count = sqlx::query_scalar!("SELECT COUNT(*) FROM wallets")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    // Records that a wallet's blocks were scanned up to the block at `height` with `hash`, continuing
+    // from `scanned_height`, and stores the outputs found in them. Nothing is stored if the wallet's
+    // progress changed or the block was disconnected in the meantime. Returns whether it was stored.
+    pub async fn add_wallet_outputs(
+        &self,
+        wallet: &str,
+        scanned_height: Option<i64>,
+        height: i64,
+        hash: &str,
+        blocks: Vec<BlockOutputs>,
+    ) -> Result<bool> {
+        let _wallet_lock = self.wallet_lock.lock().await;
+        let mut db_tx = self.pool.begin().await?;
+
+        let updated = sqlx::query!(
+            r#"
+        UPDATE wallets SET scanned_height = ?, scanned_hash = ?
+        WHERE id = ? AND scanned_height IS ?
+        AND EXISTS (SELECT 1 FROM blocks WHERE height = ? AND hash = ?)
+        "#,
+            height,
+            hash,
+            wallet,
+            scanned_height,
+            height,
+            hash
+        )
+        .execute(&mut *db_tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        for block in &blocks {
+            for output in &block.outputs {
+                sqlx::query!(
+                    r#"
+        INSERT INTO wallet_outputs (wallet, block, block_hash, txid, vout, value, pub_key, tweak, label)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+                    wallet,
+                    block.height,
+                    block.block_hash,
+                    output.txid,
+                    output.vout,
+                    output.value,
+                    output.pub_key,
+                    output.tweak,
+                    output.label,
+                )
+                .execute(&mut *db_tx)
+                .await?;
+            }
+        }
+
+        db_tx.commit().await?;
+
+        for block in blocks {
+            self.notify_wallet_subscribers(WalletEvent::Outputs(wallet.to_string(), block));
+        }
+
+        Ok(true)
+    }
+
+    // Subscribes to wallet events and returns the outputs stored for the wallet at that moment,
+    // grouped by block in height order.
+    pub async fn subscribe_wallet(
+        &self,
+        wallet: &str,
+    ) -> Result<(broadcast::Receiver<WalletEvent>, Vec<BlockOutputs>)> {
+        let _wallet_lock = self.wallet_lock.lock().await;
+        let rx = self.wallet_sub_tx.subscribe();
+
+        let records = sqlx::query!(
+            r#"
+        SELECT block, block_hash, txid, vout, value, pub_key, tweak, label
+        FROM wallet_outputs WHERE wallet = ?
+        ORDER BY block, txid, vout
+        "#,
+            wallet
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut blocks: Vec<BlockOutputs> = vec![];
+        for record in records {
+            let output = OwnedOutput {
+                txid: record.txid,
+                vout: record.vout,
+                value: record.value,
+                pub_key: record.pub_key,
+                tweak: record.tweak,
+                label: record.label.map(|label| label as u32),
+            };
+            match blocks.last_mut() {
+                Some(block) if block.height == record.block => block.outputs.push(output),
+                _ => blocks.push(BlockOutputs {
+                    height: record.block,
+                    block_hash: record.block_hash,
+                    outputs: vec![output],
+                }),
+            }
+        }
+        Ok((rx, blocks))
+    }
+
+    // Wallet events of all wallets. Use `subscribe_wallet` to also get a wallet's stored outputs.
+    pub fn subscribe_wallets(&self) -> broadcast::Receiver<WalletEvent> {
+        self.wallet_sub_tx.subscribe()
+    }
+
+    pub async fn get_mempool_transactions(&self) -> Result<Transactions> {
+        let collection: JoinedTransactionOutputCollection = sqlx::query_as!(
+            JoinedTransactionOutput,
+            r#"
+        SELECT 
+            t.txid, 
+            t.scalar, 
+            0 AS "position!: i64", 
+            o.vout, 
+            o.value, 
+            o.script_pub_key 
+        FROM mempool_transactions t
+        INNER JOIN mempool_outputs o ON t.id = o.tx
+        ORDER BY t.id, o.vout
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into();
+
+        Ok(collection.into())
+    }
+
+    pub async fn get_mempool_txids(&self) -> Result<Vec<String>> {
+        let txids = sqlx::query_scalar!("SELECT txid FROM mempool_transactions")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(txids)
+    }
+
+    pub async fn add_mempool_transactions(&self, transactions: Vec<Transaction>) -> Result<()> {
+        if transactions.is_empty() {
+            return Ok(());
+        }
+        let mut db_tx = self.pool.begin().await?;
+
+        for transaction in transactions.iter() {
+            let id = sqlx::query!(
+                "INSERT INTO mempool_transactions (id, txid, scalar) VALUES (NULL, ?, ?)",
+                transaction.txid,
+                transaction.scalar,
+            )
+            .execute(&mut *db_tx)
+            .await?
+            .last_insert_rowid();
+
+            for output in transaction.outputs.iter() {
+                sqlx::query!(
+                    r#"
+        INSERT INTO mempool_outputs (id, tx, vout, value, script_pub_key) VALUES (NULL, ?, ?, ?, ?)
+        "#,
+                    id,
+                    output.vout,
+                    output.value,
+                    output.spk,
+                )
+                .execute(&mut *db_tx)
+                .await?;
+            }
+        }
+
+        db_tx.commit().await?;
+
+        self.notify_mempool_subscribers(MempoolEvent::Added(transactions));
+
+        Ok(())
+    }
+
+    // Removes transactions that were confirmed or evicted from the mempool.
+    pub async fn remove_mempool_transactions(&self, txids: Vec<String>) -> Result<()> {
+        if txids.is_empty() {
+            return Ok(());
+        }
+        let mut db_tx = self.pool.begin().await?;
+
+        for txid in txids.iter() {
+            sqlx::query!(
+                "DELETE FROM mempool_outputs WHERE tx IN (SELECT id FROM mempool_transactions WHERE txid = ?)",
+                txid
+            )
+            .execute(&mut *db_tx)
+            .await?;
+            sqlx::query!("DELETE FROM mempool_transactions WHERE txid = ?", txid)
+                .execute(&mut *db_tx)
+                .await?;
+        }
+
+        db_tx.commit().await?;
+
+        self.notify_mempool_subscribers(MempoolEvent::Removed(txids));
+
+        Ok(())
+    }
+
+    fn notify_mempool_subscribers(&self, event: MempoolEvent) {
+        match self.mempool_sub_tx.send(event) {
+            Ok(num_sub) => debug!("Notified {} subscribers of mempool event.", num_sub),
+            Err(_) => debug!("There are no subscribers for mempool events."),
+        }
+    }
+
+    fn notify_wallet_subscribers(&self, event: WalletEvent) {
+        match self.wallet_sub_tx.send(event) {
+            Ok(num_sub) => debug!("Notified {} subscribers of wallet event.", num_sub),
+            Err(_) => debug!("There are no subscribers for wallet events."),
+        }
+    }
+
+    fn notify_subscribers(&self, event: BlockEvent) {
+        match self.sub_tx.send(event) {
+            Ok(num_sub) => debug!("Notified {} subscribers of block event.", num_sub),
+            Err(_) => debug!("There are no subscribers for block events."),
+        }
+    }
+}
+
+// Simulates a database indexed before spends were recorded.
+#[cfg(test)]
+impl Store {
+    pub(crate) async fn set_backfill_height(&self, height: i64) {
+        sqlx::query!("UPDATE backfill SET height = ?", height)
+            .execute(&self.pool)
+            .await
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures::memory_store;
+
+    fn transaction(txid: &str, values: &[i64]) -> Transaction {
+        Transaction {
+            txid: txid.into(),
+            scalar: format!("scalar-{txid}"),
+            position: 0,
+            outputs: values
+                .iter()
+                .enumerate()
+                .map(|(vout, value)| Output {
+                    vout: vout as i64,
+                    value: *value,
+                    spk: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn dust_limit(dust_limit: i64) -> TransactionFilter {
+        TransactionFilter {
+            dust_limit,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dust_limit() {
+        let store = memory_store().await;
+        let block = Block {
+            height: 1,
+            hash: String::new(),
+            transactions: vec![
+                transaction("dust", &[100, 545]),
+                transaction("mixed", &[100, 546]),
+                transaction("large", &[100_000]),
+            ],
+            spent: vec![],
+            filters: vec![],
+        };
+        store.add_block(block).await.unwrap();
+
+        let txids = |transactions: BlockTransactions| -> Vec<String> {
+            let mut txids: Vec<String> = transactions
+                .transactions
+                .into_iter()
+                .map(|tx| tx.txid)
+                .collect();
+            txids.sort();
+            txids
+        };
+
+        let all = store
+            .get_transactions_by_height(1, TransactionFilter::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(txids(all), ["dust", "large", "mixed"]);
+        let filtered = store
+            .get_transactions_by_height(1, dust_limit(546))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(txids(filtered), ["large", "mixed"]);
+        let filtered = store
+            .get_latest_transactions(dust_limit(546))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(txids(filtered), ["large", "mixed"]);
+
+        let scalars = store
+            .get_scalars_by_height(1, dust_limit(1000))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scalars.scalars, ["scalar-large"]);
+        let scalars = store
+            .get_latest_scalars(TransactionFilter::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scalars.scalars.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_spent_outputs() {
+        let store = memory_store().await;
+        let block = |height: i64, transactions, spent| Block {
+            height,
+            hash: format!("hash-{height}"),
+            transactions,
+            spent,
+            filters: vec![],
+        };
+        let spend = |txid: &str, vout, spending_txid: &str| SpentOutpoint {
+            txid: txid.into(),
+            vout,
+            spending_txid: spending_txid.into(),
+        };
+        let spent_by = |status: Option<OutputStatus>| {
+            status
+                .unwrap()
+                .spent
+                .map(|spend| (spend.txid, spend.height))
+        };
+
+        store
+            .add_block(block(1, vec![transaction("a", &[1000, 2000])], vec![]))
+            .await
+            .unwrap();
+        // Also spends an output of a transaction in the same block.
+        store
+            .add_block(block(
+                2,
+                vec![transaction("b", &[1000])],
+                vec![spend("a", 1, "b"), spend("b", 0, "c"), spend("x", 0, "b")],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            spent_by(store.get_output("a".into(), 0).await.unwrap()),
+            None
+        );
+        assert_eq!(
+            spent_by(store.get_output("a".into(), 1).await.unwrap()),
+            Some(("b".into(), 2))
+        );
+        assert_eq!(
+            spent_by(store.get_output("b".into(), 0).await.unwrap()),
+            Some(("c".into(), 2))
+        );
+        assert!(store.get_output("a".into(), 2).await.unwrap().is_none());
+
+        // Outputs spent in disconnected blocks are unspent again.
+        store.disconnect_blocks_above(1).await.unwrap();
+        assert_eq!(
+            spent_by(store.get_output("a".into(), 1).await.unwrap()),
+            None
+        );
+        assert!(store.get_output("b".into(), 0).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_backfill_block() {
+        let store = memory_store().await;
+        assert_eq!(store.get_backfill_height().await.unwrap(), 0);
+        assert_eq!(store.get_first_block_height().await.unwrap(), None);
+        for height in 1..=3 {
+            store
+                .add_block(Block {
+                    height,
+                    hash: height.to_string(),
+                    transactions: vec![transaction(&format!("tx{height}"), &[1000])],
+                    spent: vec![],
+                    filters: vec![],
+                })
+                .await
+                .unwrap();
+        }
+        store.set_backfill_height(3).await;
+        assert_eq!(store.get_first_block_height().await.unwrap(), Some(1));
+
+        let spend = SpentOutpoint {
+            txid: "tx1".into(),
+            vout: 0,
+            spending_txid: "spending".into(),
+        };
+        let block = |hash: &str| Block {
+            height: 2,
+            hash: hash.into(),
+            transactions: vec![],
+            spent: vec![spend.clone()],
+            filters: vec![],
+        };
+
+        // Blocks not in the store are not backfilled.
+        assert!(!store.backfill_block(&block("other")).await.unwrap());
+        assert!(
+            store
+                .get_output("tx1".into(), 0)
+                .await
+                .unwrap()
+                .unwrap()
+                .spent
+                .is_none()
+        );
+        assert_eq!(store.get_backfill_height().await.unwrap(), 3);
+
+        assert!(store.backfill_block(&block("2")).await.unwrap());
+        let spent = store
+            .get_output("tx1".into(), 0)
+            .await
+            .unwrap()
+            .unwrap()
+            .spent;
+        assert_eq!(
+            spent.map(|spend| (spend.txid, spend.height)),
+            Some(("spending".into(), 2))
+        );
+        assert_eq!(store.get_backfill_height().await.unwrap(), 2);
+
+        // Blocks connected after a reorg are indexed with their spends.
+        store.set_backfill_height(4).await;
+        store.disconnect_blocks_above(2).await.unwrap();
+        assert_eq!(store.get_backfill_height().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cut_through() {
+        let store = memory_store().await;
+        let spend = |txid: &str, vout| SpentOutpoint {
+            txid: txid.into(),
+            vout,
+            spending_txid: "spending".into(),
+        };
+        store
+            .add_block(Block {
+                height: 1,
+                hash: String::new(),
+                transactions: vec![
+                    transaction("spent", &[1000, 2000]),
+                    transaction("partially-spent", &[1000, 2000]),
+                ],
+                spent: vec![],
+                filters: vec![],
+            })
+            .await
+            .unwrap();
+        store
+            .add_block(Block {
+                height: 2,
+                hash: String::new(),
+                transactions: vec![],
+                spent: vec![
+                    spend("spent", 0),
+                    spend("spent", 1),
+                    spend("partially-spent", 0),
+                ],
+                filters: vec![],
+            })
+            .await
+            .unwrap();
+
+        let cut_through = TransactionFilter {
+            cut_through: true,
+            ..Default::default()
+        };
+        let scalars = store
+            .get_scalars_by_height(1, cut_through)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scalars.scalars, ["scalar-partially-spent"]);
+        let transactions = store
+            .get_transactions_by_height(1, cut_through)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(transactions.transactions.len(), 1);
+        // Full history clients still get all transactions.
+        let scalars = store
+            .get_scalars_by_height(1, TransactionFilter::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scalars.scalars.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_height_range() {
+        let store = memory_store().await;
+        let blocks = [
+            vec![transaction("a", &[1000])],
+            vec![],
+            vec![transaction("b", &[1000, 2000]), transaction("c", &[1000])],
+            vec![transaction("d", &[1000])],
+        ];
+        for (height, transactions) in blocks.into_iter().enumerate() {
+            store
+                .add_block(Block {
+                    height: height as i64 + 1,
+                    hash: String::new(),
+                    transactions,
+                    spent: vec![],
+                    filters: vec![],
+                })
+                .await
+                .unwrap();
+        }
+
+        let scalars = store
+            .get_scalars_by_height_range(2, 10, TransactionFilter::default())
+            .await
+            .unwrap();
+        let heights: Vec<i64> = scalars.iter().map(|block| block.height).collect();
+        assert_eq!(heights, [2, 3, 4]);
+        assert!(scalars[0].scalars.is_empty());
+        assert_eq!(scalars[1].scalars.len(), 2);
+        assert_eq!(scalars[2].scalars, ["scalar-d"]);
+
+        let transactions = store
+            .get_transactions_by_height_range(1, 3, TransactionFilter::default())
+            .await
+            .unwrap();
+        let heights: Vec<i64> = transactions.iter().map(|block| block.height).collect();
+        assert_eq!(heights, [1, 2, 3]);
+        assert!(transactions[1].transactions.is_empty());
+        let outputs: usize = transactions[2]
+            .transactions
+            .iter()
+            .map(|tx| tx.outputs.len())
+            .sum();
+        assert_eq!(outputs, 3);
+
+        // Blocks 1 to 3 hold 3 transactions.
+        let exceeding = |from, to, max| store.get_height_exceeding_transactions(from, to, max);
+        assert_eq!(exceeding(1, 4, 2).await.unwrap(), Some(3));
+        assert_eq!(exceeding(1, 4, 3).await.unwrap(), Some(4));
+        assert_eq!(exceeding(2, 4, 3).await.unwrap(), None);
+        assert_eq!(exceeding(1, 3, 3).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_height() {
+        let store = memory_store().await;
+        let filter = TransactionFilter::default();
+        assert!(store.get_latest_scalars(filter).await.unwrap().is_none());
+
+        store
+            .add_block(Block {
+                height: 1,
+                hash: "hash-1".into(),
+                transactions: vec![],
+                spent: vec![],
+                filters: vec![],
+            })
+            .await
+            .unwrap();
+
+        // Synced block without eligible transactions.
+        let scalars = store
+            .get_scalars_by_height(1, filter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((scalars.height, scalars.block_hash.as_str()), (1, "hash-1"));
+        assert!(scalars.scalars.is_empty());
+        let transactions = store
+            .get_latest_transactions(filter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(transactions.block_hash, "hash-1");
+        assert!(transactions.transactions.is_empty());
+
+        // Not synced yet.
+        assert!(
+            store
+                .get_scalars_by_height(2, filter)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            store
+                .get_transactions_by_height(2, filter)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_order() {
+        let store = memory_store().await;
+        let positioned = |txid, position, values: &[i64]| Transaction {
+            position,
+            ..transaction(txid, values)
+        };
+        let mut unordered = positioned("c", 3, &[1, 2, 3]);
+        unordered.outputs.reverse();
+        store
+            .add_block(Block {
+                height: 1,
+                hash: String::new(),
+                transactions: vec![
+                    unordered,
+                    positioned("a", 1, &[1]),
+                    positioned("b", 2, &[1, 2]),
+                ],
+                spent: vec![],
+                filters: vec![],
+            })
+            .await
+            .unwrap();
+
+        let summary = |transactions: Vec<Transaction>| -> Vec<(String, Vec<i64>)> {
+            transactions
+                .into_iter()
+                .map(|tx| (tx.txid, tx.outputs.iter().map(|o| o.vout).collect()))
+                .collect()
+        };
+        let expected = vec![
+            ("a".to_string(), vec![0]),
+            ("b".to_string(), vec![0, 1]),
+            ("c".to_string(), vec![0, 1, 2]),
+        ];
+
+        let filter = TransactionFilter::default();
+        let block = store
+            .get_transactions_by_height(1, filter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary(block.transactions), expected);
+        let range = store
+            .get_transactions_by_height_range(1, 1, filter)
+            .await
+            .unwrap();
+        assert_eq!(summary(range[0].transactions.clone()), expected);
+        let scalars = store
+            .get_scalars_by_height(1, filter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scalars.scalars, ["scalar-a", "scalar-b", "scalar-c"]);
+    }
+
+    #[tokio::test]
+    async fn test_wallets() {
+        let store = memory_store().await;
+        let mut wallet = Wallet {
+            id: "wallet".into(),
+            scan_key: "scan".into(),
+            spend_key: "spend".into(),
+            birthday: 10,
+            labels: vec![],
+            scanned_height: None,
+        };
+        store.add_wallet(&wallet).await.unwrap();
+        assert_eq!(
+            store.get_wallet("wallet".into()).await.unwrap(),
+            Some(wallet.clone())
+        );
+
+        wallet.birthday = 5;
+        wallet.labels = vec![1, 1337];
+        store.add_wallet(&wallet).await.unwrap();
+        assert_eq!(
+            store.get_wallet("wallet".into()).await.unwrap(),
+            Some(wallet)
+        );
+        assert!(store.get_wallet("unknown".into()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wallet_outputs() {
+        let store = memory_store().await;
+        let wallet = Wallet {
+            id: "wallet".into(),
+            scan_key: "scan".into(),
+            spend_key: "spend".into(),
+            birthday: 1,
+            labels: vec![],
+            scanned_height: None,
+        };
+        store.add_wallet(&wallet).await.unwrap();
+        for height in 1..=2 {
+            store
+                .add_block(Block {
+                    height,
+                    hash: height.to_string(),
+                    transactions: vec![],
+                    spent: vec![],
+                    filters: vec![],
+                })
+                .await
+                .unwrap();
+        }
+        let outputs = |height: i64| BlockOutputs {
+            height,
+            block_hash: height.to_string(),
+            outputs: vec![OwnedOutput {
+                txid: "aa".repeat(32),
+                vout: height,
+                value: 1000,
+                pub_key: "key".into(),
+                tweak: "tweak".into(),
+                label: Some(1),
+            }],
+        };
+        let scanned_height = |store: Store| async move {
+            store
+                .get_wallet("wallet".into())
+                .await
+                .unwrap()
+                .unwrap()
+                .scanned_height
+        };
+
+        // Progress is only recorded on top of the stored progress and for stored blocks.
+        assert!(
+            !store
+                .add_wallet_outputs("wallet", Some(1), 2, "2", vec![outputs(2)])
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .add_wallet_outputs("wallet", None, 2, "other", vec![outputs(2)])
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .add_wallet_outputs("wallet", None, 2, "2", vec![outputs(1), outputs(2)])
+                .await
+                .unwrap()
+        );
+        assert_eq!(scanned_height(store.clone()).await, Some(2));
+
+        let (mut rx, stored) = store.subscribe_wallet("wallet").await.unwrap();
+        assert_eq!(stored, [outputs(1), outputs(2)]);
+
+        // Outputs of disconnected blocks are dropped and scanning continues from the fork point.
+        store.disconnect_blocks_above(1).await.unwrap();
+        assert!(matches!(rx.try_recv(), Ok(WalletEvent::Disconnected(block)) if block.height == 2));
+        assert_eq!(scanned_height(store.clone()).await, Some(1));
+        let (_, stored) = store.subscribe_wallet("wallet").await.unwrap();
+        assert_eq!(stored, [outputs(1)]);
+
+        // Registering again with the same birthday and labels keeps the outputs, other ones drop
+        // them.
+        store.add_wallet(&wallet).await.unwrap();
+        assert!(rx.try_recv().is_err());
+        store
+            .add_wallet(&Wallet {
+                labels: vec![1],
+                ..wallet
+            })
+            .await
+            .unwrap();
+        assert!(matches!(rx.try_recv(), Ok(WalletEvent::Registered(id)) if id == "wallet"));
+        assert_eq!(scanned_height(store.clone()).await, None);
+        let (_, stored) = store.subscribe_wallet("wallet").await.unwrap();
+        assert!(stored.is_empty());
     }
 }
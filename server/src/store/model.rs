@@ -2,10 +2,13 @@ use std::collections::HashMap;
 
 use serde::Serialize;
 
+use crate::scan::OwnedOutput;
+
 // Intermediate utility types.
 pub struct JoinedTransactionOutput {
     pub txid: String,
     pub scalar: String,
+    pub position: i64,
     pub vout: i64,
     pub value: i64,
     pub script_pub_key: String,
@@ -20,17 +23,119 @@ pub struct Block {
     pub height: i64,
     pub hash: String,
     pub transactions: Vec<Transaction>,
+    // Outpoints spent by the block's transactions. Only set for blocks coming from the syncer.
+    pub spent: Vec<SpentOutpoint>,
+    // Compact filters of the block. Only set for blocks coming from the syncer.
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpentOutpoint {
+    pub txid: String,
+    pub vout: i64,
+    pub spending_txid: String,
+}
+
+// Events sent to block subscribers. A block is disconnected when it was removed from the store
+// because of a chain reorganization, subscribers should undo what they saw in it.
+#[derive(Debug, Clone)]
+pub enum BlockEvent {
+    Connected(Block),
+    Disconnected(Block),
 }
+
+// Events sent to wallet subscribers, in the order the stored wallet outputs changed.
+#[derive(Debug, Clone)]
+pub enum WalletEvent {
+    // A wallet with this id was registered, or registered again with a different birthday or labels
+    // which drops its outputs so they are scanned again.
+    Registered(String),
+    // Outputs found for the wallet with this id.
+    Outputs(String, BlockOutputs),
+    // A block was disconnected, the outputs of all wallets in it were dropped.
+    Disconnected(DisconnectedBlock),
+}
+
+// Events sent to mempool subscribers. Transactions are removed from the mempool index when they
+// are confirmed or evicted from the node's mempool.
+#[derive(Debug, Clone)]
+pub enum MempoolEvent {
+    Added(Vec<Transaction>),
+    Removed(Vec<String>),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DisconnectedBlock {
+    pub height: i64,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    // Taproot output keys of the block's eligible transactions.
+    NewUtxos,
+    // Hashed outpoints spent by the block's transactions.
+    Spent,
+}
+
+impl FilterKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterKind::NewUtxos => "new-utxos",
+            FilterKind::Spent => "spent",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub kind: FilterKind,
+    // Hex encoded filter.
+    pub data: String,
+}
+
+#[derive(Serialize)]
+pub struct BlockFilter {
+    pub height: i64,
+    pub block_hash: String,
+    pub filter: String,
+}
+
+// Filters applied to the transactions of a block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransactionFilter {
+    // Omit transactions whose taproot outputs are all below this value.
+    pub dust_limit: i64,
+    // Omit transactions whose taproot outputs are all spent (BIP-352 cut-through).
+    pub cut_through: bool,
+}
+
 #[derive(Serialize)]
 pub struct Scalar {
     pub scalar: String,
 }
 
 #[derive(Serialize)]
-pub struct Scalars {
+pub struct BlockScalars {
+    pub height: i64,
+    pub block_hash: String,
     pub scalars: Vec<String>,
 }
 
+#[derive(Serialize)]
+pub struct BlockTransactions {
+    pub height: i64,
+    pub block_hash: String,
+    pub transactions: Vec<Transaction>,
+}
+
+// Page of a height range. `next` is the height to continue from, `None` on the last page.
+#[derive(Serialize)]
+pub struct BlockRange<T> {
+    pub blocks: Vec<T>,
+    pub next: Option<i64>,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct Output {
     pub vout: i64,
@@ -38,13 +143,71 @@ pub struct Output {
     pub spk: String,
 }
 
+#[derive(Serialize)]
+pub struct Spend {
+    pub txid: String,
+    pub height: i64,
+}
+
+#[derive(Serialize)]
+pub struct OutputStatus {
+    pub txid: String,
+    pub vout: i64,
+    // Height of the block with the output.
+    pub height: i64,
+    pub value: i64,
+    pub spk: String,
+    pub spent: Option<Spend>,
+}
+
+// Watch-only wallet registered for output scanning. Blocks below `birthday` are not scanned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wallet {
+    pub id: String,
+    // Hex encoded scan secret key.
+    pub scan_key: String,
+    // Hex encoded spend public key.
+    pub spend_key: String,
+    pub birthday: i64,
+    pub labels: Vec<u32>,
+    // Height of the last scanned block, `None` before the first one. Set by the wallet scanner.
+    pub scanned_height: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WalletId {
+    pub id: String,
+}
+
+// Outputs of a block owned by a wallet.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockOutputs {
+    pub height: i64,
+    pub block_hash: String,
+    pub outputs: Vec<OwnedOutput>,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct Transaction {
     pub txid: String,
     pub scalar: String,
+    // Index of the transaction in its block. Not serialized, responses are ordered by it.
+    #[serde(skip)]
+    pub position: i64,
     pub outputs: Vec<Output>,
 }
 
+impl Transaction {
+    // Highest value of the taproot outputs, transactions below a dust limit can be filtered on it.
+    pub fn max_output_value(&self) -> i64 {
+        self.outputs
+            .iter()
+            .map(|output| output.value)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
 #[derive(Serialize)]
 pub struct Transactions {
     pub transactions: Vec<Transaction>,
@@ -64,6 +227,7 @@ impl From<JoinedTransactionOutputCollection> for Option<Transaction> {
         // because this comes from a join sql query.
         let txid = value.0[0].txid.clone();
         let scalar = value.0[0].scalar.clone();
+        let position = value.0[0].position;
         let mut outputs = vec![];
 
         for record in value.0.iter() {
@@ -77,36 +241,40 @@ impl From<JoinedTransactionOutputCollection> for Option<Transaction> {
         Some(Transaction {
             txid,
             scalar,
+            position,
             outputs,
         })
     }
 }
 
+// Transactions keep the order in which their first record appears, outputs the order of their
+// records. Queries order records by position in the block and vout.
 impl From<JoinedTransactionOutputCollection> for Transactions {
     fn from(value: JoinedTransactionOutputCollection) -> Self {
-        let mut tx_map: HashMap<String, Transaction> = HashMap::new();
-
-        for tx_record in value.0.iter() {
-            let txid = tx_record.txid.clone();
-            let scalar = tx_record.scalar.clone();
+        let mut transactions: Vec<Transaction> = vec![];
+        let mut tx_index: HashMap<String, usize> = HashMap::new();
 
+        for tx_record in value.0.into_iter() {
             let output = Output {
                 vout: tx_record.vout,
                 value: tx_record.value,
-                spk: tx_record.script_pub_key.clone(),
+                spk: tx_record.script_pub_key,
             };
 
-            tx_map
-                .entry(txid.clone())
-                .and_modify(|tx| tx.outputs.push(output.clone()))
-                .or_insert_with(|| Transaction {
-                    txid,
-                    scalar,
-                    outputs: vec![output],
-                });
+            match tx_index.get(&tx_record.txid) {
+                Some(&i) => transactions[i].outputs.push(output),
+                None => {
+                    tx_index.insert(tx_record.txid.clone(), transactions.len());
+                    transactions.push(Transaction {
+                        txid: tx_record.txid,
+                        scalar: tx_record.scalar,
+                        position: tx_record.position,
+                        outputs: vec![output],
+                    });
+                }
+            }
         }
 
-        let transactions = tx_map.into_values().collect::<Vec<Transaction>>();
         Transactions { transactions }
     }
 }
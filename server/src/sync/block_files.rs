@@ -0,0 +1,671 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bitcoincore_rpc::bitcoin::{
+    Amount, Block, BlockHash, OutPoint, PublicKey, ScriptBuf, Transaction, TxOut, Txid,
+    block::Header,
+    consensus::{Decodable, deserialize},
+    hashes::{Hash, sha256d},
+    script::Builder,
+};
+use tracing::info;
+
+use super::rpc::{BitcionRpc, BlockWithPrevouts};
+use crate::{Error, Result};
+
+// Size of the magic bytes and the length prefix in front of each record in blk/rev files.
+const RECORD_PREFIX_SIZE: u64 = 8;
+// Size of the checksum after each record in rev files.
+const UNDO_CHECKSUM_SIZE: u64 = 32;
+const HEADER_SIZE: usize = 80;
+
+// Block source reading blocks (`blk*.dat`) and undo data (`rev*.dat`) directly from the blocks
+// directory of a Bitcoin Core datadir. Prevouts come from the undo data, so neither RPC nor txindex
+// is needed. Files obfuscated with the key in `xor.dat` are supported.
+//
+// The block files are indexed once when opened, blocks written by the node afterwards are not
+// seen. The best chain is the longest chain of blocks found in the files, which is good enough
+// for initial sync, the RPC syncer takes over (and handles reorgs) once caught up.
+pub struct BlockFiles {
+    blocks_dir: PathBuf,
+    xor_key: [u8; 8],
+    // Best chain block hashes indexed by height.
+    chain: Vec<BlockHash>,
+    locations: HashMap<BlockHash, BlockLocation>,
+    // Position of each best chain block among the best chain blocks in the same file. Blocks are
+    // connected in chain order, so this is where its undo data is expected in the rev file.
+    undo_positions: HashMap<BlockHash, usize>,
+    undo_files: Mutex<HashMap<u32, Arc<Vec<Record>>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    // Offset of the record data (after magic and length) in the file.
+    offset: u64,
+    size: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BlockLocation {
+    file: u32,
+    record: Record,
+    prev_blockhash: BlockHash,
+}
+
+impl BlockFiles {
+    pub fn open(blocks_dir: impl AsRef<Path>) -> Result<Self> {
+        let blocks_dir = blocks_dir.as_ref().to_path_buf();
+        info!("Indexing block files in {}.", blocks_dir.display());
+
+        let xor_key = match std::fs::read(blocks_dir.join("xor.dat")) {
+            Ok(key) => key
+                .try_into()
+                .map_err(|_| Error::BlockFile("xor.dat is not 8 bytes".into()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => [0; 8],
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut locations = HashMap::new();
+        let mut file = 0;
+        loop {
+            let path = blocks_dir.join(format!("blk{file:05}.dat"));
+            if !path.exists() {
+                break;
+            }
+            let mut reader = XorReader::open(&path, xor_key)?;
+            for record in read_records(&mut reader, 0)? {
+                let mut header = [0; HEADER_SIZE];
+                reader.read_exact_at(record.offset, &mut header)?;
+                let header: Header = deserialize(&header).map_err(|_| {
+                    Error::BlockFile(format!("invalid header in {}", path.display()))
+                })?;
+                let location = BlockLocation {
+                    file,
+                    record,
+                    prev_blockhash: header.prev_blockhash,
+                };
+                locations.insert(header.block_hash(), location);
+            }
+            file += 1;
+        }
+
+        let chain = best_chain(&locations)?;
+
+        // The genesis block has no undo data.
+        let mut undo_positions = HashMap::new();
+        let mut blocks_per_file: HashMap<u32, usize> = HashMap::new();
+        for hash in chain.iter().skip(1) {
+            let count = blocks_per_file.entry(locations[hash].file).or_default();
+            undo_positions.insert(*hash, *count);
+            *count += 1;
+        }
+
+        info!(
+            "Indexed {} blocks in {} block files, best chain height: {}.",
+            locations.len(),
+            file,
+            chain.len() - 1
+        );
+
+        Ok(Self {
+            blocks_dir,
+            xor_key,
+            chain,
+            locations,
+            undo_positions,
+            undo_files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn location(&self, height: u64) -> Result<(BlockHash, BlockLocation)> {
+        let hash = self
+            .chain
+            .get(height as usize)
+            .ok_or_else(|| Error::BlockFile(format!("no block at height {height}")))?;
+        Ok((*hash, self.locations[hash]))
+    }
+
+    fn read_block(&self, location: &BlockLocation) -> Result<Block> {
+        let path = self.blocks_dir.join(format!("blk{:05}.dat", location.file));
+        let mut reader = XorReader::open(&path, self.xor_key)?;
+        let mut data = vec![0; location.record.size as usize];
+        reader.read_exact_at(location.record.offset, &mut data)?;
+        deserialize(&data)
+            .map_err(|_| Error::BlockFile(format!("invalid block in {}", path.display())))
+    }
+
+    // Find and parse the undo data of a block in the rev file with the same number as its blk
+    // file, returning the outputs spent by each non-coinbase transaction. Undo records don't say
+    // which block they belong to. Their checksum commits to the previous block hash, which
+    // siblings share, so a record also has to match the block's transactions and inputs. The
+    // record at the expected position is tried first, then all records of the file (e.g. because
+    // of stale blocks in the same file).
+    fn read_block_undo(
+        &self,
+        hash: &BlockHash,
+        location: &BlockLocation,
+        block: &Block,
+    ) -> Result<Vec<Vec<TxOut>>> {
+        let path = self.blocks_dir.join(format!("rev{:05}.dat", location.file));
+        let mut reader = XorReader::open(&path, self.xor_key)?;
+
+        // The lock is only held for the cache lookup, workers of parallel syncing read their own
+        // file handles.
+        let cached = self
+            .undo_files
+            .lock()
+            .expect("undo files lock is not poisoned")
+            .get(&location.file)
+            .cloned();
+        let records = match cached {
+            Some(records) => records,
+            None => {
+                let records = Arc::new(read_records(&mut reader, UNDO_CHECKSUM_SIZE)?);
+                self.undo_files
+                    .lock()
+                    .expect("undo files lock is not poisoned")
+                    .insert(location.file, records.clone());
+                records
+            }
+        };
+
+        let expected = self.undo_positions.get(hash).copied().unwrap_or_default();
+        let candidates =
+            std::iter::once(expected).chain((0..records.len()).filter(|i| *i != expected));
+        for record in candidates.filter_map(|i| records.get(i)) {
+            let mut data = vec![0; record.size as usize + UNDO_CHECKSUM_SIZE as usize];
+            reader.read_exact_at(record.offset, &mut data)?;
+            let (undo, checksum) = data.split_at(record.size as usize);
+            let undo_hash =
+                sha256d::Hash::hash(&[location.prev_blockhash.as_byte_array(), undo].concat());
+            if undo_hash.as_byte_array() != checksum {
+                continue;
+            }
+            if let Ok(tx_prevouts) = parse_block_undo(undo)
+                && undo_matches_block(&tx_prevouts, block)
+            {
+                return Ok(tx_prevouts);
+            }
+        }
+
+        // The node might still have been writing the rev file when it was read, read it again
+        // next time.
+        self.undo_files
+            .lock()
+            .expect("undo files lock is not poisoned")
+            .remove(&location.file);
+        Err(Error::BlockFile(format!(
+            "no undo data for block {} in {}",
+            hash,
+            path.display()
+        )))
+    }
+}
+
+// Undo data has one entry per non-coinbase transaction with one spent output per input.
+fn undo_matches_block(tx_prevouts: &[Vec<TxOut>], block: &Block) -> bool {
+    let spending_txs = block.txdata.iter().skip(1);
+    tx_prevouts.len() == spending_txs.len()
+        && tx_prevouts
+            .iter()
+            .zip(spending_txs)
+            .all(|(prevouts, tx)| prevouts.len() == tx.input.len())
+}
+
+impl BitcionRpc for BlockFiles {
+    fn get_block_by_height(&self, height: u64) -> Result<Block> {
+        let (_, location) = self.location(height)?;
+        self.read_block(&location)
+    }
+
+    fn get_block_hash_by_height(&self, height: u64) -> Result<BlockHash> {
+        Ok(self.location(height)?.0)
+    }
+
+    fn get_block_with_prevouts(&self, height: u64) -> Result<Option<BlockWithPrevouts>> {
+        let (hash, location) = self.location(height)?;
+        let block = self.read_block(&location)?;
+
+        // Blocks with only a coinbase transaction spend nothing, the genesis block has no undo
+        // data at all.
+        if block.txdata.len() <= 1 {
+            let prevouts = vec![vec![]; block.txdata.len()];
+            return Ok(Some(BlockWithPrevouts { block, prevouts }));
+        }
+
+        let tx_prevouts = self.read_block_undo(&hash, &location, &block)?;
+
+        // Undo data has no entry for the coinbase transaction.
+        let prevouts = std::iter::once(vec![]).chain(tx_prevouts).collect();
+        Ok(Some(BlockWithPrevouts { block, prevouts }))
+    }
+
+    fn get_chain_tip(&self) -> Result<usize> {
+        Ok(self.chain.len() - 1)
+    }
+
+    fn get_transaction(&self, txid: &Txid) -> Result<Transaction> {
+        Err(Error::BlockFile(format!(
+            "can not look up transaction {txid} in block files"
+        )))
+    }
+
+    // Block files do not contain the mempool.
+    fn get_mempool_txids(&self) -> Result<Vec<Txid>> {
+        Ok(vec![])
+    }
+
+    fn get_unspent_output(&self, outpoint: &OutPoint) -> Result<Option<TxOut>> {
+        Err(Error::BlockFile(format!(
+            "can not look up unspent output {outpoint} in block files"
+        )))
+    }
+}
+
+// Build the best chain from the genesis block (the block without previous block) to the highest
+// block that connects to it. Height is used instead of chain work.
+fn best_chain(locations: &HashMap<BlockHash, BlockLocation>) -> Result<Vec<BlockHash>> {
+    let mut children: HashMap<BlockHash, Vec<BlockHash>> = HashMap::new();
+    for (hash, location) in locations.iter() {
+        children
+            .entry(location.prev_blockhash)
+            .or_default()
+            .push(*hash);
+    }
+
+    let genesis = children
+        .get(&BlockHash::all_zeros())
+        .and_then(|genesis| genesis.first())
+        .ok_or_else(|| Error::BlockFile("genesis block not found".into()))?;
+
+    // Of competing tips with the same height, the one stored first was seen first by the node.
+    let position = |hash: &BlockHash| {
+        let location = &locations[hash];
+        (location.file, location.record.offset)
+    };
+    let mut tip = (0, *genesis);
+    let mut stack = vec![(0, *genesis)];
+    while let Some((height, hash)) = stack.pop() {
+        if height > tip.0 || (height == tip.0 && position(&hash) < position(&tip.1)) {
+            tip = (height, hash);
+        }
+        for child in children.get(&hash).into_iter().flatten() {
+            stack.push((height + 1, *child));
+        }
+    }
+
+    let mut chain = vec![tip.1];
+    while chain.len() <= tip.0 {
+        let hash = chain.last().expect("chain is not empty");
+        chain.push(locations[hash].prev_blockhash);
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+// Read the positions of all records in a blk or rev file. Each record is prefixed by the network
+// magic and its length and followed by `trailer` bytes. Reading stops at the first record that
+// is not (fully) written yet, files are preallocated with zeros.
+fn read_records(reader: &mut XorReader, trailer: u64) -> Result<Vec<Record>> {
+    let file_len = reader.len()?;
+    let mut records = vec![];
+    let mut position = 0;
+    while position + RECORD_PREFIX_SIZE <= file_len {
+        let mut prefix = [0; RECORD_PREFIX_SIZE as usize];
+        reader.read_exact_at(position, &mut prefix)?;
+        if prefix[..4] == [0; 4] {
+            break;
+        }
+        let size = u32::from_le_bytes(prefix[4..].try_into().expect("4 bytes"));
+        let offset = position + RECORD_PREFIX_SIZE;
+        let end = offset + size as u64 + trailer;
+        if end > file_len {
+            break;
+        }
+        records.push(Record { offset, size });
+        position = end;
+    }
+    Ok(records)
+}
+
+// Parse serialized `CBlockUndo`: the outputs spent by each non-coinbase transaction of a block.
+fn parse_block_undo(data: &[u8]) -> io::Result<Vec<Vec<TxOut>>> {
+    let mut reader = Cursor::new(data);
+    let tx_count = read_compact_size(&mut reader)?;
+    let mut txs = Vec::with_capacity(tx_count.min(data.len() as u64) as usize);
+    for _ in 0..tx_count {
+        let coin_count = read_compact_size(&mut reader)?;
+        let mut coins = Vec::with_capacity(coin_count.min(data.len() as u64) as usize);
+        for _ in 0..coin_count {
+            coins.push(read_coin(&mut reader)?);
+        }
+        txs.push(coins);
+    }
+    if reader.position() != data.len() as u64 {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    Ok(txs)
+}
+
+// Parse one spent output in undo format: height and coinbase flag, a legacy version byte, the
+// compressed amount and the compressed script.
+fn read_coin(reader: &mut Cursor<&[u8]>) -> io::Result<TxOut> {
+    let code = read_varint(reader)?;
+    let height = code >> 1;
+    if height > 0 {
+        let _version = read_varint(reader)?;
+    }
+    let value = Amount::from_sat(decompress_amount(read_varint(reader)?));
+    let script_pubkey = read_compressed_script(reader)?;
+    Ok(TxOut {
+        value,
+        script_pubkey,
+    })
+}
+
+fn read_compressed_script(reader: &mut Cursor<&[u8]>) -> io::Result<ScriptBuf> {
+    let kind = read_varint(reader)?;
+    let script = match kind {
+        // P2PKH
+        0x00 => {
+            let hash: [u8; 20] = read_array(reader)?;
+            ScriptBuf::from_bytes([&[0x76, 0xa9, 0x14], &hash[..], &[0x88, 0xac]].concat())
+        }
+        // P2SH
+        0x01 => {
+            let hash: [u8; 20] = read_array(reader)?;
+            ScriptBuf::from_bytes([&[0xa9, 0x14], &hash[..], &[0x87]].concat())
+        }
+        // P2PK with compressed public key.
+        0x02 | 0x03 => {
+            let x: [u8; 32] = read_array(reader)?;
+            ScriptBuf::from_bytes([&[0x21, kind as u8], &x[..], &[0xac]].concat())
+        }
+        // P2PK with uncompressed public key, stored compressed.
+        0x04 | 0x05 => {
+            let x: [u8; 32] = read_array(reader)?;
+            let compressed = [&[kind as u8 - 2], &x[..]].concat();
+            let mut public_key = PublicKey::from_slice(&compressed)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            public_key.compressed = false;
+            Builder::new()
+                .push_key(&public_key)
+                .push_opcode(bitcoincore_rpc::bitcoin::opcodes::all::OP_CHECKSIG)
+                .into_script()
+        }
+        _ => {
+            let size = kind - 6;
+            // MAX_SCRIPT_SIZE, larger scripts are stored as OP_RETURN.
+            if size > 10_000 {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            let mut script = vec![0; size as usize];
+            reader.read_exact(&mut script)?;
+            ScriptBuf::from_bytes(script)
+        }
+    };
+    Ok(script)
+}
+
+fn read_array<const N: usize>(reader: &mut Cursor<&[u8]>) -> io::Result<[u8; N]> {
+    let mut array = [0; N];
+    reader.read_exact(&mut array)?;
+    Ok(array)
+}
+
+fn read_compact_size(reader: &mut Cursor<&[u8]>) -> io::Result<u64> {
+    let size = bitcoincore_rpc::bitcoin::VarInt::consensus_decode(reader)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+    Ok(size.0)
+}
+
+// Bitcoin Core's `VARINT`, MSB base-128 encoding where each continuation adds one.
+fn read_varint(reader: &mut Cursor<&[u8]>) -> io::Result<u64> {
+    let mut n: u64 = 0;
+    loop {
+        let [byte] = read_array(reader)?;
+        if n > (u64::MAX >> 7) {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        n = (n << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+        n = n.checked_add(1).ok_or(io::ErrorKind::InvalidData)?;
+    }
+}
+
+// Inverse of Bitcoin Core's `CompressAmount`.
+fn decompress_amount(x: u64) -> u64 {
+    if x == 0 {
+        return 0;
+    }
+    let mut x = x - 1;
+    let mut e = x % 10;
+    x /= 10;
+    let mut n = if e < 9 {
+        let d = (x % 9) + 1;
+        x /= 9;
+        x * 10 + d
+    } else {
+        x + 1
+    };
+    while e > 0 {
+        n *= 10;
+        e -= 1;
+    }
+    n
+}
+
+// Reader for blk/rev files, bytes are XORed with the obfuscation key based on their position in
+// the file.
+struct XorReader {
+    file: File,
+    key: [u8; 8],
+}
+
+impl XorReader {
+    fn open(path: &Path, key: [u8; 8]) -> Result<Self> {
+        Ok(Self {
+            file: File::open(path)?,
+            key,
+        })
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)?;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte ^= self.key[(offset as usize + i) % self.key.len()];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoincore_rpc::bitcoin::{
+        Network, OutPoint, PubkeyHash, Sequence, TxIn, Witness, absolute::LockTime,
+        consensus::serialize, constants::genesis_block, transaction::Version,
+    };
+
+    use super::*;
+
+    const XOR_KEY: [u8; 8] = [0x13, 0x37, 0x42, 0x00, 0xde, 0xad, 0xbe, 0xef];
+    const MAGIC: [u8; 4] = [0xfa, 0xbf, 0xb5, 0xda];
+
+    // Inverse of `read_varint`.
+    fn write_varint(buf: &mut Vec<u8>, n: u64) {
+        let mut n = n;
+        let mut tmp = vec![];
+        loop {
+            tmp.push((n & 0x7f) as u8 | if tmp.is_empty() { 0x00 } else { 0x80 });
+            if n <= 0x7f {
+                break;
+            }
+            n = (n >> 7) - 1;
+        }
+        buf.extend(tmp.iter().rev());
+    }
+
+    // Bitcoin Core's `CompressAmount`.
+    fn compress_amount(n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        let mut n = n;
+        let mut e = 0;
+        while n.is_multiple_of(10) && e < 9 {
+            n /= 10;
+            e += 1;
+        }
+        if e < 9 {
+            let d = n % 10;
+            n /= 10;
+            1 + (n * 9 + d - 1) * 10 + e
+        } else {
+            1 + (n - 1) * 10 + 9
+        }
+    }
+
+    fn write_coin(buf: &mut Vec<u8>, txout: &TxOut, height: u64, coinbase: bool) {
+        write_varint(buf, height * 2 + coinbase as u64);
+        if height > 0 {
+            write_varint(buf, 0);
+        }
+        write_varint(buf, compress_amount(txout.value.to_sat()));
+        let script = txout.script_pubkey.as_bytes();
+        if txout.script_pubkey.is_p2pkh() {
+            write_varint(buf, 0x00);
+            buf.extend(&script[3..23]);
+        } else if txout.script_pubkey.is_p2pk() && script.len() == 67 {
+            // Uncompressed key, stored as x coordinate with the parity of y.
+            write_varint(buf, 0x04 | (script[65] & 1) as u64);
+            buf.extend(&script[2..34]);
+        } else {
+            write_varint(buf, script.len() as u64 + 6);
+            buf.extend(script);
+        }
+    }
+
+    fn write_file(path: &Path, data: &[u8]) {
+        let data: Vec<u8> = data
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ XOR_KEY[i % XOR_KEY.len()])
+            .collect();
+        std::fs::write(path, data).unwrap();
+    }
+
+    fn write_record(file: &mut Vec<u8>, data: &[u8]) {
+        file.extend(MAGIC);
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(data);
+    }
+
+    fn write_undo_record(file: &mut Vec<u8>, undo: &[u8], prev_blockhash: &BlockHash) {
+        write_record(file, undo);
+        let checksum = sha256d::Hash::hash(&[prev_blockhash.as_byte_array(), undo].concat());
+        file.extend(checksum.as_byte_array());
+    }
+
+    #[test]
+    fn test_read_blocks_and_undo_data() {
+        let genesis = genesis_block(Network::Regtest);
+
+        // Outputs spent in block 1: the genesis coinbase output (P2PK with uncompressed key), a
+        // P2PKH output and a P2TR output.
+        let genesis_output = genesis.txdata[0].output[0].clone();
+        let p2pkh_output = TxOut {
+            value: Amount::from_sat(1_000_000),
+            script_pubkey: ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([0xab; 20])),
+        };
+        let p2tr_output = TxOut {
+            value: Amount::from_sat(123_456_789),
+            script_pubkey: ScriptBuf::from_bytes([&[0x51, 0x20], &[0xcd; 32][..]].concat()),
+        };
+        let spent = [
+            (genesis_output, 0, true),
+            (p2pkh_output, 5, false),
+            (p2tr_output, 700_000, false),
+        ];
+
+        let spending_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: (0..spent.len())
+                .map(|i| TxIn {
+                    previous_output: OutPoint::new(genesis.txdata[0].compute_txid(), i as u32),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::default(),
+                })
+                .collect(),
+            output: vec![spent[2].0.clone()],
+        };
+        let mut block = genesis.clone();
+        block.header.prev_blockhash = genesis.block_hash();
+        block.txdata.push(spending_tx);
+
+        // A stale block at height 1 that is not part of the best chain.
+        let mut stale_block = genesis.clone();
+        stale_block.header.prev_blockhash = genesis.block_hash();
+        stale_block.header.nonce = 1;
+
+        let mut undo = vec![0x01, spent.len() as u8];
+        for (txout, height, coinbase) in spent.iter() {
+            write_coin(&mut undo, txout, *height, *coinbase);
+        }
+
+        let blocks_dir = std::env::temp_dir().join(format!(
+            "silent-payments-block-files-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&blocks_dir).unwrap();
+        std::fs::write(blocks_dir.join("xor.dat"), XOR_KEY).unwrap();
+
+        // Blocks are not stored in chain order. Files end with preallocated zeros.
+        let mut blk = vec![];
+        write_record(&mut blk, &serialize(&block));
+        write_record(&mut blk, &serialize(&stale_block));
+        write_record(&mut blk, &serialize(&genesis));
+        blk.extend([0; 64]);
+        write_file(&blocks_dir.join("blk00000.dat"), &blk);
+
+        // Undo data of the stale block is first and has a valid checksum as it has the same
+        // previous block.
+        let mut rev = vec![];
+        write_undo_record(&mut rev, &[0x00], &genesis.block_hash());
+        write_undo_record(&mut rev, &undo, &genesis.block_hash());
+        rev.extend([0; 64]);
+        write_file(&blocks_dir.join("rev00000.dat"), &rev);
+
+        let block_files = BlockFiles::open(&blocks_dir).unwrap();
+
+        assert_eq!(block_files.get_chain_tip().unwrap(), 1);
+        assert_eq!(
+            block_files.get_block_hash_by_height(0).unwrap(),
+            genesis.block_hash()
+        );
+        assert_eq!(block_files.get_block_by_height(1).unwrap(), block);
+
+        let block_with_prevouts = block_files.get_block_with_prevouts(1).unwrap().unwrap();
+        let expected: Vec<TxOut> = spent.iter().map(|(txout, _, _)| txout.clone()).collect();
+        assert_eq!(block_with_prevouts.block, block);
+        assert_eq!(block_with_prevouts.prevouts, vec![vec![], expected]);
+
+        let genesis_with_prevouts = block_files.get_block_with_prevouts(0).unwrap().unwrap();
+        assert_eq!(genesis_with_prevouts.prevouts, vec![vec![]]);
+
+        std::fs::remove_dir_all(&blocks_dir).unwrap();
+    }
+}
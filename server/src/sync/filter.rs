@@ -0,0 +1,143 @@
+use bitcoincore_rpc::bitcoin::{
+    Block, OutPoint, Transaction,
+    bip158::BlockFilterWriter,
+    hashes::{Hash, sha256},
+};
+
+use crate::{serialize_outpoint, store::model};
+
+// Build the BIP-158 style filter over the x-only public keys of the taproot outputs of the block's
+// eligible transactions. Like basic block filters, the filter is keyed with the block hash so
+// clients can match their candidate output keys with `BlockFilter::match_any`.
+pub fn new_utxos_filter(block: &Block, eligible_txs: &[&Transaction]) -> model::Filter {
+    let mut data = vec![];
+    let mut writer = BlockFilterWriter::new(&mut data, block);
+    for txout in eligible_txs.iter().flat_map(|tx| tx.output.iter()) {
+        if txout.script_pubkey.is_p2tr() {
+            // OP_1 0x20 <32 byte x-only public key>
+            writer.add_element(&txout.script_pubkey.as_bytes()[2..]);
+        }
+    }
+    writer.finish().expect("writing to a vec does not fail");
+
+    model::Filter {
+        kind: model::FilterKind::NewUtxos,
+        data: hex::encode(data),
+    }
+}
+
+// Hash of an outpoint spent in the block with `block_hash`: the first 8 bytes of
+// sha256(outpoint || block hash), with the outpoint serialized as in transactions and the block hash
+// in internal byte order. Salting with the block hash keeps the elements unique across blocks.
+pub fn spent_outpoint_hash(outpoint: &OutPoint, block_hash: &[u8; 32]) -> [u8; 8] {
+    let msg = [serialize_outpoint(outpoint).as_slice(), block_hash].concat();
+    let hash = sha256::Hash::hash(&msg).to_byte_array();
+    hash[..8].try_into().expect("8 byte slice")
+}
+
+// Build the BIP-158 style filter over the hashes of all outpoints spent by the block's transactions
+// so wallets can check whether any of their coins were spent in the block.
+pub fn spent_filter(block: &Block) -> model::Filter {
+    let block_hash = block.block_hash().to_byte_array();
+    let mut data = vec![];
+    let mut writer = BlockFilterWriter::new(&mut data, block);
+    for txin in block
+        .txdata
+        .iter()
+        .filter(|tx| !tx.is_coinbase())
+        .flat_map(|tx| tx.input.iter())
+    {
+        writer.add_element(&spent_outpoint_hash(&txin.previous_output, &block_hash));
+    }
+    writer.finish().expect("writing to a vec does not fail");
+
+    model::Filter {
+        kind: model::FilterKind::Spent,
+        data: hex::encode(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoincore_rpc::bitcoin::{
+        Amount, ScriptBuf, TxIn, TxOut, Txid, absolute::LockTime, bip158::BlockFilter,
+        transaction::Version,
+    };
+
+    use super::*;
+    use crate::tests::fixtures::chain;
+
+    fn p2tr(key: [u8; 32]) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(1000),
+            script_pubkey: ScriptBuf::from_bytes([&[0x51, 0x20], key.as_slice()].concat()),
+        }
+    }
+
+    #[test]
+    fn test_new_utxos_filter() {
+        let block = chain(1).pop().unwrap();
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![
+                p2tr([1; 32]),
+                TxOut {
+                    value: Amount::from_sat(1000),
+                    script_pubkey: ScriptBuf::new(),
+                },
+                p2tr([2; 32]),
+            ],
+        };
+
+        let filter = new_utxos_filter(&block, &[&tx]);
+        assert_eq!(filter.kind, model::FilterKind::NewUtxos);
+        let filter = BlockFilter::new(&hex::decode(filter.data).unwrap());
+        let block_hash = block.block_hash();
+
+        let matches = |key: [u8; 32]| {
+            filter
+                .match_any(&block_hash, [key.as_slice()].into_iter())
+                .unwrap()
+        };
+        assert!(matches([1; 32]));
+        assert!(matches([2; 32]));
+        assert!(!matches([3; 32]));
+    }
+
+    #[test]
+    fn test_spent_filter() {
+        let mut block = chain(1).pop().unwrap();
+        let outpoint = |byte, vout| OutPoint::new(Txid::from_byte_array([byte; 32]), vout);
+        block.txdata.push(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: [outpoint(1, 0), outpoint(2, 1)]
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![],
+        });
+
+        let filter = spent_filter(&block);
+        assert_eq!(filter.kind, model::FilterKind::Spent);
+        let filter = BlockFilter::new(&hex::decode(filter.data).unwrap());
+        let block_hash = block.block_hash();
+
+        let matches = |outpoint: OutPoint| {
+            let hash = spent_outpoint_hash(&outpoint, &block_hash.to_byte_array());
+            filter
+                .match_any(&block_hash, [hash.as_slice()].into_iter())
+                .unwrap()
+        };
+        assert!(matches(outpoint(1, 0)));
+        assert!(matches(outpoint(2, 1)));
+        assert!(!matches(outpoint(2, 0)));
+        // The coinbase input is not a spent outpoint.
+        assert!(!matches(block.txdata[0].input[0].previous_output));
+    }
+}
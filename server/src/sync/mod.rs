@@ -1,29 +1,70 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
     time::Duration,
-    usize,
 };
 
-use bitcoincore_rpc::bitcoin::{Block, OutPoint, TxOut};
-use secp256k1::{PublicKey, Scalar};
-use tokio::time::sleep;
-use tracing::{debug, info};
-
-use crate::{
-    Result, calculate_input_hash, has_output_witness_version_greater_v1, has_taproot_outputs,
-    store::model, try_get_input_public_key,
+use bitcoincore_rpc::bitcoin::{Block, BlockHash, OutPoint, Transaction, TxOut, Txid};
+use futures::{StreamExt, stream};
+use tokio::{
+    task::{JoinHandle, spawn_blocking},
+    time::sleep,
 };
+use tracing::{debug, info, warn};
+
+use crate::{Error, Result, compute_tweak, has_taproot_outputs, store::model};
 use crate::{config::SyncerConfig, store::Store};
 
+mod block_files;
+mod filter;
 mod rpc;
+mod zmq;
 
-pub use rpc::BitcionRpc;
+pub use block_files::BlockFiles;
+pub use rpc::{BitcionRpc, BlockWithPrevouts};
+pub use zmq::BlockNotifications;
+
+// Interval between checks for a new chain tip.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// Interval between checks for a new chain tip when subscribed to block notifications, in case a
+// notification was missed.
+const NOTIFIED_POLL_INTERVAL: Duration = Duration::from_secs(60);
+// Interval between mempool syncs.
+const MEMPOOL_INTERVAL: Duration = Duration::from_secs(5);
+// Maximum number of new mempool transactions processed per round. Rounds follow each other without
+// waiting until the backlog is processed.
+const MEMPOOL_BATCH_SIZE: usize = 500;
+// Maximum number of blocks backfilled per round before checking for a new tip again.
+const BACKFILL_BATCH_SIZE: i64 = 100;
 
 pub struct Syncer<C: BitcionRpc> {
-    client: C,
+    client: Arc<C>,
     store: Store,
-    prevout_cache: PrevoutCache,
+    // Shared with the worker threads of parallel syncing.
+    prevout_cache: Arc<Mutex<PrevoutCache>>,
     sync_from: i64,
+    // Whether the node returns prevouts with blocks. If not, prevouts are fetched per input which
+    // requires `-txindex`. Shared with the worker threads, the first one to get a block without
+    // prevouts turns it off.
+    block_prevouts: Arc<AtomicBool>,
+    // Number of blocks fetched and processed concurrently while catching up.
+    parallelism: usize,
+    zmq_block_url: Option<String>,
+    block_notifications: Option<BlockNotifications>,
+}
+
+// Syncs the node's mempool in its own task, independent of syncing and waiting for blocks.
+struct MempoolSyncer<C: BitcionRpc> {
+    client: Arc<C>,
+    store: Store,
+    prevout_cache: Arc<Mutex<PrevoutCache>>,
+    parallelism: usize,
+    // Mempool transactions that were processed successfully, eligible or not.
+    seen: HashSet<Txid>,
 }
 
 #[derive(Debug)]
@@ -51,14 +92,14 @@ impl PrevoutCache {
         ret
     )]
     fn insert(&mut self, key: OutPoint, value: Vec<TxOut>) {
-        if self.map.len() >= self.size {
-            if let Some(oldest_key) = self.order.pop_front() {
-                info!("Size limit reached, removing oldest item: {:?}", oldest_key);
-                self.map.remove(&oldest_key);
-            }
+        if self.map.len() >= self.size
+            && let Some(oldest_key) = self.order.pop_front()
+        {
+            info!("Size limit reached, removing oldest item: {:?}", oldest_key);
+            self.map.remove(&oldest_key);
         }
 
-        self.order.push_back(key.clone());
+        self.order.push_back(key);
         self.map.insert(key, value);
     }
 
@@ -74,186 +115,659 @@ impl PrevoutCache {
     }
 }
 
-impl<C: BitcionRpc> Syncer<C> {
+impl<C: BitcionRpc + Send + Sync + 'static> MempoolSyncer<C> {
+    // Index the eligible transactions that entered the node's mempool since the last call and
+    // remove the ones that left it because they were confirmed or evicted. Returns whether new
+    // transactions are left for the next round.
+    async fn sync(&mut self) -> Result<bool> {
+        let client = self.client.clone();
+        let mempool: HashSet<Txid> = spawn_blocking(move || client.get_mempool_txids())
+            .await??
+            .into_iter()
+            .collect();
+        self.seen.retain(|txid| mempool.contains(txid));
+
+        let (stored, removed): (Vec<String>, Vec<String>) = self
+            .store
+            .get_mempool_txids()
+            .await?
+            .into_iter()
+            .partition(|txid| Txid::from_str(txid).is_ok_and(|txid| mempool.contains(&txid)));
+        let stored: HashSet<String> = stored.into_iter().collect();
+        if !removed.is_empty() {
+            info!(
+                "Removing {} transactions that left the mempool.",
+                removed.len()
+            );
+        }
+        self.store.remove_mempool_transactions(removed).await?;
+
+        let new: Vec<Txid> = mempool
+            .into_iter()
+            .filter(|txid| !self.seen.contains(txid) && !stored.contains(&txid.to_string()))
+            .collect();
+        let left = new.len() > MEMPOOL_BATCH_SIZE;
+        if left {
+            info!(
+                "Processing {} of {} new mempool transactions, the rest follows next round.",
+                MEMPOOL_BATCH_SIZE,
+                new.len()
+            );
+        }
+
+        // Transactions are fetched on blocking worker threads. Failed ones are not marked as seen
+        // so they are retried next round, e.g. when a parent was not available yet.
+        let mut results = stream::iter(new.into_iter().take(MEMPOOL_BATCH_SIZE))
+            .map(|txid| {
+                let client = self.client.clone();
+                let prevout_cache = self.prevout_cache.clone();
+                spawn_blocking(move || {
+                    let tx =
+                        fetch_and_index_mempool_transaction(client.as_ref(), &prevout_cache, &txid);
+                    (txid, tx)
+                })
+            })
+            .buffer_unordered(self.parallelism);
+        let mut added = vec![];
+        let mut processed = false;
+        while let Some(result) = results.next().await {
+            let (txid, tx) = result?;
+            match tx {
+                Ok(tx) => {
+                    self.seen.insert(txid);
+                    processed = true;
+                    added.extend(tx);
+                }
+                Err(err) => warn!("Skipping mempool transaction {}: {}", txid, err),
+            }
+        }
+        if !added.is_empty() {
+            info!("Adding {} eligible mempool transactions.", added.len());
+        }
+        self.store.add_mempool_transactions(added).await?;
+        // Without progress the remaining transactions wait for the next interval.
+        Ok(left && processed)
+    }
+
+    // Sync the mempool every `MEMPOOL_INTERVAL` once the blocks are synced, and right away while a
+    // backlog of new transactions is left.
+    async fn run(mut self) {
+        loop {
+            if self.store.is_synced() {
+                match self.sync().await {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(err) => warn!("Syncing mempool failed: {}", err),
+                }
+            }
+            sleep(MEMPOOL_INTERVAL).await;
+        }
+    }
+}
+
+impl<C: BitcionRpc + Send + Sync + 'static> Syncer<C> {
     pub fn new(cfg: SyncerConfig, client: C, store: Store) -> Self {
         info!("Initializing Syncer.");
         let prevout_cache = PrevoutCache::new(cfg.cache_size);
 
         Self {
-            client,
+            client: Arc::new(client),
             store,
-            prevout_cache,
+            prevout_cache: Arc::new(Mutex::new(prevout_cache)),
             sync_from: cfg.sync_from,
+            block_prevouts: Arc::new(AtomicBool::new(true)),
+            parallelism: cfg.parallelism.max(1),
+            zmq_block_url: cfg.zmq_block_url,
+            block_notifications: None,
         }
     }
 
-    pub fn get_prevout(&mut self, outpoint: &OutPoint) -> Result<TxOut> {
-        if let Some(previous_outputs) = self.prevout_cache.get(outpoint) {
-            info!("Got previous outputs from cache.");
-            let txout = previous_outputs
-                .get(outpoint.vout as usize)
-                .expect("vout is present int tx");
-            return Ok(txout.clone());
+    fn mempool_syncer(&self) -> MempoolSyncer<C> {
+        MempoolSyncer {
+            client: self.client.clone(),
+            store: self.store.clone(),
+            prevout_cache: self.prevout_cache.clone(),
+            parallelism: self.parallelism,
+            seen: HashSet::new(),
         }
+    }
 
-        info!(
-            "Previous outputs not in cache. Using Bitcoin Core RPC client to fetch and insert them into cache."
-        );
-        let previous_outputs = self.client.get_transaction(&outpoint.txid)?.output;
-        let txout = previous_outputs
-            .get(outpoint.vout as usize)
-            .expect("vout is present int tx");
-        self.prevout_cache
-            .insert(outpoint.clone(), previous_outputs.clone());
-        Ok(txout.clone())
-    }
-
-    fn process_block(&mut self, block: Block, height: u64) -> Result<model::Block> {
-        let block_hash = block.block_hash().to_string();
-        info!(
-            "Processing new block with hash: {} with {} transactions.",
-            block_hash,
-            block.txdata.len()
-        );
-        let mut eligible_txs = vec![];
-        for tx in block.txdata.iter() {
-            // Filter coinbase.
-            if tx.is_coinbase() {
-                debug!("Transaction is coinbase. Skipping.");
-                continue;
+    pub fn get_prevout(&self, outpoint: &OutPoint) -> Result<TxOut> {
+        get_cached_prevout(self.client.as_ref(), &self.prevout_cache, outpoint)
+    }
+
+    // Run a blocking RPC call on a worker thread so it does not stall the runtime.
+    async fn rpc<T, F>(&self, call: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&C) -> Result<T> + Send + 'static,
+    {
+        let client = self.client.clone();
+        spawn_blocking(move || call(client.as_ref())).await?
+    }
+
+    // Fetch and process the block at `height` on a worker thread.
+    fn spawn_index_block(&self, height: u64) -> JoinHandle<Result<(BlockHash, model::Block)>> {
+        let client = self.client.clone();
+        let prevout_cache = self.prevout_cache.clone();
+        let block_prevouts = self.block_prevouts.clone();
+        spawn_blocking(move || {
+            fetch_and_index_block(client.as_ref(), &prevout_cache, &block_prevouts, height)
+        })
+    }
+
+    // Walk back from `height` until the block hash in the store matches the node's hash for that
+    // height (or there is no stored block anymore) and return this fork point.
+    async fn find_fork_point(&self, height: u64) -> Result<u64> {
+        let mut height = height;
+        while height > 0 {
+            let Some(stored_hash) = self.store.get_block_hash(height as i64).await? else {
+                break;
+            };
+            let node_hash = self
+                .rpc(move |client| client.get_block_hash_by_height(height))
+                .await?
+                .to_string();
+            if stored_hash == node_hash {
+                break;
             }
+            warn!(
+                "Stored block {} at height {} is not in the best chain anymore.",
+                stored_hash, height
+            );
+            height -= 1;
+        }
+        Ok(height)
+    }
 
-            // The transaction contains at least one BIP341 taproot output (note: spent transactions
-            // optionally can be skipped by only considering transactions with at least one unspent taproot
-            // output)
-            if !has_taproot_outputs(tx) {
-                debug!("Transaction has no taproot outputs. Skipping.");
-                continue;
+    // Check whether the stored blocks up to `height` are still in the best chain. If not, roll the
+    // store back to the fork point and return it so the new branch is indexed from there.
+    async fn handle_reorg(&self, synced_blocks: u64, height: u64) -> Result<u64> {
+        let fork_point = self.find_fork_point(height).await?;
+        if fork_point < synced_blocks {
+            let disconnected = self
+                .store
+                .disconnect_blocks_above(fork_point as i64)
+                .await?;
+            if disconnected > 0 {
+                warn!(
+                    "Chain reorganization: disconnected {} blocks above fork point {}.",
+                    disconnected, fork_point
+                );
+                return Ok(fork_point);
             }
+        }
+        Ok(synced_blocks)
+    }
 
-            let prevouts = tx
-                .input
-                .iter()
-                .map(|txin| self.get_prevout(&txin.previous_output))
-                .collect::<Result<Vec<TxOut>>>()?;
+    // Whether a block with `prev_blockhash` builds on the block stored at `synced_blocks`.
+    async fn extends_synced_chain(
+        &self,
+        prev_blockhash: &BlockHash,
+        synced_blocks: u64,
+    ) -> Result<bool> {
+        let stored_hash = self.store.get_block_hash(synced_blocks as i64).await?;
+        Ok(stored_hash.is_none_or(|hash| hash == prev_blockhash.to_string()))
+    }
+
+    // Fetch and process the blocks above `synced_blocks` up to `to` on blocking worker threads,
+    // `parallelism` blocks at a time, and store them in height order. Stops at the first block that
+    // does not build on the synced chain and rolls the store back to the fork point. Returns the
+    // synced height.
+    async fn sync_parallel(&mut self, synced_blocks: u64, to: u64) -> Result<u64> {
+        let mut synced_blocks = synced_blocks;
+        let mut blocks = stream::iter(synced_blocks + 1..=to)
+            .map(|height| self.spawn_index_block(height))
+            .buffered(self.parallelism);
 
-            // The transaction does not spend an output with SegWit version > 1
-            if has_output_witness_version_greater_v1(&prevouts) {
-                debug!("Transaction spends an output with SegWit version > 1. Skipping.");
-                continue;
+        while let Some(result) = blocks.next().await {
+            let (prev_blockhash, block) = result??;
+            if !self
+                .extends_synced_chain(&prev_blockhash, synced_blocks)
+                .await?
+            {
+                // Blocks still in flight belong to the stale branch, drop them.
+                drop(blocks);
+                return self.handle_reorg(synced_blocks, synced_blocks).await;
             }
+            info!("Proccessed block {} successfully", block.height);
+            self.store.add_block(block).await?;
+            synced_blocks += 1;
+        }
+        Ok(synced_blocks)
+    }
 
-            // The transaction has at least one input from the Inputs For Shared Secret Derivation list. In
-            // case there was no eligible input the sum of public keys will be None.
-            let public_keys_for_shared_secret_derivation: Vec<PublicKey> = tx
-                .input
-                .iter()
-                .zip(&prevouts)
-                .map(|(input, prevout)| try_get_input_public_key(input, prevout))
-                .flatten()
-                .collect();
+    // Sync blocks until the store is at the node's chain tip, handling chain reorganizations on the
+    // way. Returns the synced height.
+    pub async fn sync_to_tip(&mut self, synced_blocks: u64) -> Result<u64> {
+        let mut synced_blocks = synced_blocks;
+        loop {
+            let chain_tip = self.rpc(|client| client.get_chain_tip()).await? as u64;
+            info!("Got best block height from RPC: {}", chain_tip);
+
+            if self.parallelism > 1 && chain_tip.saturating_sub(synced_blocks) > 1 {
+                info!(
+                    "Syncing blocks {} to {} with parallelism {}",
+                    synced_blocks + 1,
+                    chain_tip,
+                    self.parallelism
+                );
+                synced_blocks = self.sync_parallel(synced_blocks, chain_tip).await?;
+            } else if synced_blocks < chain_tip {
+                info!("Best block height greater than synced height. Fetching new block...");
+                let (prev_blockhash, block) = self.spawn_index_block(synced_blocks + 1).await??;
+
+                if !self
+                    .extends_synced_chain(&prev_blockhash, synced_blocks)
+                    .await?
+                {
+                    synced_blocks = self.handle_reorg(synced_blocks, synced_blocks).await?;
+                    continue;
+                }
+                synced_blocks += 1;
+                info!("Proccessed block successfully");
 
-            if public_keys_for_shared_secret_derivation.len() == 0 {
-                debug!("Transaction does not have any inputs for shared secret derivation");
-                continue;
+                self.store.add_block(block).await?;
+            } else {
+                // The tip might have been replaced or the best chain might be shorter now.
+                let fork_point = self.handle_reorg(synced_blocks, chain_tip).await?;
+                if fork_point == synced_blocks {
+                    return Ok(synced_blocks);
+                }
+                synced_blocks = fork_point;
             }
+        }
+    }
 
-            let (first_input, rest) = public_keys_for_shared_secret_derivation
-                .split_first()
-                .unwrap();
-            let input_public_key_sum = rest
-                .iter()
-                .try_fold(*first_input, |acc, item| acc.combine(item))
-                .unwrap();
+    async fn get_synced_height(&self) -> Result<u64> {
+        let synced_blocks = self
+            .store
+            .get_synced_blocks_height()
+            .await?
+            .unwrap_or(self.sync_from) as u64;
+        Ok(synced_blocks)
+    }
 
-            let lowest_outpoint = tx
-                .input
-                .iter()
-                .map(|txin| txin.previous_output)
-                .min()
-                .expect("Transaction has at least one input");
-
-            let input_hash = calculate_input_hash(lowest_outpoint, input_public_key_sum);
-            let secp = secp256k1::Secp256k1::new();
-            let scalar = input_public_key_sum
-                .mul_tweak(&secp, &Scalar::from_be_bytes(input_hash).unwrap())
-                .unwrap();
-            let scalar_bytes = scalar.serialize();
-            let scalar_hex = hex::encode(scalar_bytes);
+    // Sync up to the tip of the block source once, e.g. from block files before syncing with RPC.
+    pub async fn catch_up(&mut self) -> Result<u64> {
+        let synced_blocks = self.get_synced_height().await?;
+        info!("Catching up from height: {}", synced_blocks);
+        self.sync_to_tip(synced_blocks).await
+    }
 
-            let relevant_outputs: Vec<model::Output> = tx
-                .output
+    // Re-fetch blocks that were indexed before spends and filters were recorded, from the top down,
+    // and record them. The eligible transactions of the new UTXOs filter are the ones already stored.
+    // Returns whether blocks are left to backfill.
+    pub async fn backfill(&mut self) -> Result<bool> {
+        let Some(first_height) = self.store.get_first_block_height().await? else {
+            return Ok(false);
+        };
+        let backfill_height = self.store.get_backfill_height().await?;
+        if backfill_height <= first_height {
+            return Ok(false);
+        }
+        let from = (backfill_height - BACKFILL_BATCH_SIZE).max(first_height);
+        info!("Backfilling blocks {} to {}.", from, backfill_height - 1);
+
+        let mut blocks = stream::iter((from..backfill_height).rev())
+            .map(|height| {
+                let client = self.client.clone();
+                spawn_blocking(move || client.get_block_by_height(height as u64))
+            })
+            .buffered(self.parallelism);
+        let mut height = backfill_height;
+        while let Some(result) = blocks.next().await {
+            let block = result??;
+            height -= 1;
+            let stored_txids: HashSet<String> = self
+                .store
+                .get_transactions_by_height(height, model::TransactionFilter::default())
+                .await?
+                .map(|stored| stored.transactions.into_iter().map(|tx| tx.txid).collect())
+                .unwrap_or_default();
+            let eligible_txs: Vec<&Transaction> = block
+                .txdata
                 .iter()
-                .enumerate()
-                .filter_map(|(i, out)| {
-                    if out.script_pubkey.is_p2tr() {
-                        Some(model::Output {
-                            vout: i as i64,
-                            value: out.value.to_sat() as i64,
-                            spk: out.script_pubkey.to_hex_string(),
-                        })
-                    } else {
-                        None
-                    }
-                })
+                .filter(|tx| stored_txids.contains(&tx.compute_txid().to_string()))
                 .collect();
-
-            let eligible_tx = model::Transaction {
-                txid: tx.compute_txid().to_string(),
-                scalar: scalar_hex,
-                outputs: relevant_outputs,
+            let filters = vec![
+                filter::new_utxos_filter(&block, &eligible_txs),
+                filter::spent_filter(&block),
+            ];
+            let block = model::Block {
+                height,
+                hash: block.block_hash().to_string(),
+                transactions: vec![],
+                spent: block
+                    .txdata
+                    .iter()
+                    .filter(|tx| !tx.is_coinbase())
+                    .flat_map(spent_outpoints)
+                    .collect(),
+                filters,
             };
-            info!("Adding transaction to eligible transactions");
-            eligible_txs.push(eligible_tx);
+            if !self.store.backfill_block(&block).await? {
+                warn!(
+                    "Stored block {} is not in the node's best chain, stopping backfill.",
+                    height
+                );
+                return Ok(false);
+            }
         }
-        info!(
-            "Eligible transactions after filtering: {}",
-            eligible_txs.len()
-        );
+        Ok(from > first_height)
+    }
 
-        Ok(model::Block {
-            height: height as i64,
-            hash: block_hash,
-            transactions: eligible_txs,
-        })
+    // Wait until a block notification arrives or the poll interval elapsed. Falls back to polling
+    // if the notification subscription fails.
+    async fn wait_for_block(&mut self) {
+        let Some(notifications) = self.block_notifications.as_mut() else {
+            info!(
+                "Already synced up to this height. Waiting {:?}.",
+                POLL_INTERVAL
+            );
+            sleep(POLL_INTERVAL).await;
+            return;
+        };
+
+        info!("Already synced up to this height. Waiting for block notification.");
+        tokio::select! {
+            notification = notifications.recv() => match notification {
+                Ok(hash) => info!("Got notification for block {}", hash),
+                Err(err) => {
+                    warn!("Block notifications failed, falling back to polling: {}", err);
+                    self.block_notifications = None;
+                }
+            },
+            _ = sleep(NOTIFIED_POLL_INTERVAL) => {}
+        }
     }
 
     pub async fn sync_from(&mut self) -> Result<()> {
-        let mut synced_blocks = self
-            .store
-            .get_synced_blocks_height()
-            .await?
-            .unwrap_or(self.sync_from) as u64;
+        let synced_blocks = self.get_synced_height().await?;
+
+        if let Some(url) = &self.zmq_block_url {
+            match BlockNotifications::connect(url).await {
+                Ok(notifications) => self.block_notifications = Some(notifications),
+                Err(err) => warn!(
+                    "Could not subscribe to block notifications, polling instead: {}",
+                    err
+                ),
+            }
+        }
+
+        let mempool = tokio::spawn(self.mempool_syncer().run());
+        let result = self.sync_blocks(synced_blocks).await;
+        mempool.abort();
+        result
+    }
 
+    async fn sync_blocks(&mut self, synced_blocks: u64) -> Result<()> {
+        let mut synced_blocks = synced_blocks;
         info!("Start syncing blocks from height: {}", synced_blocks);
         loop {
-            let chain_tip = self.client.get_chain_tip()? as u64;
-            info!("Got best block height from RPC: {}", chain_tip);
+            synced_blocks = self.sync_to_tip(synced_blocks).await?;
+            self.store.set_synced();
+            // Check for a new tip between batches while backfilling.
+            match self.backfill().await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => warn!("Backfilling blocks failed: {}", err),
+            }
+            self.wait_for_block().await;
+        }
+    }
+}
 
-            if synced_blocks < chain_tip {
-                info!("Best block height greater than synced height. Fetching new block...");
-                let block = self.client.get_block_by_height(synced_blocks + 1)?;
-                synced_blocks += 1;
+// Process a block into the eligible transactions and their tweaks. `get_prevouts` returns the
+// outputs spent by the transaction at the given index of the block.
+fn index_block(
+    block: Block,
+    height: u64,
+    mut get_prevouts: impl FnMut(usize, &Transaction) -> Result<Vec<TxOut>>,
+) -> Result<model::Block> {
+    let block_hash = block.block_hash().to_string();
+    info!(
+        "Processing new block with hash: {} with {} transactions.",
+        block_hash,
+        block.txdata.len()
+    );
+    let mut eligible_txs = vec![];
+    let mut indexed_txs = vec![];
+    let mut spent = vec![];
+    let mut skipped_txs = 0;
+    for (i, tx) in block.txdata.iter().enumerate() {
+        // Filter coinbase.
+        if tx.is_coinbase() {
+            debug!("Transaction is coinbase. Skipping.");
+            continue;
+        }
 
-                let block = self.process_block(block, synced_blocks)?;
-                info!("Proccessed block successfully");
+        // Any transaction can spend indexed outputs, eligible or not.
+        spent.extend(spent_outpoints(tx));
 
-                self.store.add_block(block).await?;
+        // The transaction contains at least one BIP341 taproot output (note: spent transactions
+        // optionally can be skipped by only considering transactions with at least one unspent taproot
+        // output)
+        if !has_taproot_outputs(tx) {
+            debug!("Transaction has no taproot outputs. Skipping.");
+            continue;
+        }
+
+        let prevouts = get_prevouts(i, tx)?;
+
+        match index_transaction(tx, i as i64, &prevouts) {
+            Ok(Some(eligible_tx)) => {
+                info!("Adding transaction to eligible transactions");
+                eligible_txs.push(eligible_tx);
+                indexed_txs.push(tx);
+            }
+            Ok(None) => continue,
+            Err(Error::Tweak(err)) => {
+                warn!(
+                    "Skipping transaction {}, no tweak can be computed: {:?}",
+                    tx.compute_txid(),
+                    err
+                );
+                skipped_txs += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    info!(
+        "Eligible transactions after filtering: {}, skipped eligible transactions: {}",
+        eligible_txs.len(),
+        skipped_txs
+    );
+    let filters = vec![
+        filter::new_utxos_filter(&block, &indexed_txs),
+        filter::spent_filter(&block),
+    ];
+
+    Ok(model::Block {
+        height: height as i64,
+        hash: block_hash,
+        transactions: eligible_txs,
+        spent,
+        filters,
+    })
+}
+
+fn spent_outpoints(tx: &Transaction) -> impl Iterator<Item = model::SpentOutpoint> {
+    let spending_txid = tx.compute_txid().to_string();
+    tx.input.iter().map(move |txin| model::SpentOutpoint {
+        txid: txin.previous_output.txid.to_string(),
+        vout: txin.previous_output.vout as i64,
+        spending_txid: spending_txid.clone(),
+    })
+}
+
+// Compute the tweak of a transaction with taproot outputs spending `prevouts`. Returns `None` if the
+// transaction is not eligible.
+fn index_transaction(
+    tx: &Transaction,
+    position: i64,
+    prevouts: &[TxOut],
+) -> Result<Option<model::Transaction>> {
+    let Some(tweak) = compute_tweak(&tx.input, prevouts)? else {
+        return Ok(None);
+    };
+    let scalar_hex = hex::encode(tweak.serialize());
+
+    let relevant_outputs: Vec<model::Output> = tx
+        .output
+        .iter()
+        .enumerate()
+        .filter_map(|(i, out)| {
+            if out.script_pubkey.is_p2tr() {
+                Some(model::Output {
+                    vout: i as i64,
+                    value: out.value.to_sat() as i64,
+                    spk: out.script_pubkey.to_hex_string(),
+                })
             } else {
-                info!("Already synced up to this height. Waiting 5 seconds.");
-                sleep(Duration::from_secs(5)).await;
+                None
             }
+        })
+        .collect();
+
+    Ok(Some(model::Transaction {
+        txid: tx.compute_txid().to_string(),
+        scalar: scalar_hex,
+        position,
+        outputs: relevant_outputs,
+    }))
+}
+
+// Get an output spent by a transaction from the prevout cache, or fetch the transaction that created
+// it and cache its outputs. The lock is not held while fetching.
+fn get_cached_prevout<C: BitcionRpc>(
+    client: &C,
+    prevout_cache: &Mutex<PrevoutCache>,
+    outpoint: &OutPoint,
+) -> Result<TxOut> {
+    let cached = prevout_cache
+        .lock()
+        .expect("prevout cache lock is not poisoned")
+        .get(outpoint)
+        .cloned();
+    let previous_outputs = match cached {
+        Some(previous_outputs) => {
+            info!("Got previous outputs from cache.");
+            previous_outputs
+        }
+        None => {
+            info!(
+                "Previous outputs not in cache. Using Bitcoin Core RPC client to fetch and insert them into cache."
+            );
+            let previous_outputs = client.get_transaction(&outpoint.txid)?.output;
+            prevout_cache
+                .lock()
+                .expect("prevout cache lock is not poisoned")
+                .insert(*outpoint, previous_outputs.clone());
+            previous_outputs
         }
+    };
+    // The node returned a transaction without the spent output.
+    previous_outputs
+        .get(outpoint.vout as usize)
+        .cloned()
+        .ok_or(Error::InvalidInput)
+}
+
+// Fetch a mempool transaction and compute its tweak. Outputs it spends come from the UTXO set or,
+// for unconfirmed parents, from the prevout cache.
+fn fetch_and_index_mempool_transaction<C: BitcionRpc>(
+    client: &C,
+    prevout_cache: &Mutex<PrevoutCache>,
+    txid: &Txid,
+) -> Result<Option<model::Transaction>> {
+    let tx = client.get_transaction(txid)?;
+    if !has_taproot_outputs(&tx) {
+        return Ok(None);
     }
+    let prevouts = tx
+        .input
+        .iter()
+        .map(
+            |txin| match client.get_unspent_output(&txin.previous_output)? {
+                Some(txout) => Ok(txout),
+                None => get_cached_prevout(client, prevout_cache, &txin.previous_output),
+            },
+        )
+        .collect::<Result<Vec<TxOut>>>()?;
+    // Unconfirmed transactions have no position in a block.
+    index_transaction(&tx, 0, &prevouts)
+}
+
+// Fetch and process the block at `height` without touching the syncer's state so it can run on a
+// blocking worker thread. Returns the hash of the previous block together with the processed block.
+fn fetch_and_index_block<C: BitcionRpc>(
+    client: &C,
+    prevout_cache: &Mutex<PrevoutCache>,
+    block_prevouts: &AtomicBool,
+    height: u64,
+) -> Result<(BlockHash, model::Block)> {
+    let block_with_prevouts = match block_prevouts.load(Ordering::Relaxed) {
+        true => client.get_block_with_prevouts(height)?,
+        false => None,
+    };
+    let (block, block_prevouts) = match block_with_prevouts {
+        Some(block) => (block.block, Some(block.prevouts)),
+        None => {
+            if block_prevouts.swap(false, Ordering::Relaxed) {
+                warn!(
+                    "Node does not return prevouts with blocks, fetching prevouts per input instead (requires txindex)."
+                );
+            }
+            (client.get_block_by_height(height)?, None)
+        }
+    };
+    let prev_blockhash = block.header.prev_blockhash;
+    let block = index_block(block, height, |i, tx| match &block_prevouts {
+        Some(block_prevouts) => Ok(block_prevouts[i].clone()),
+        None => tx
+            .input
+            .iter()
+            .map(|txin| get_cached_prevout(client, prevout_cache, &txin.previous_output))
+            .collect(),
+    })?;
+    Ok((prev_blockhash, block))
 }
 
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    use bitcoincore_rpc::bitcoin::{Amount, ScriptBuf, Txid};
+    use bitcoincore_rpc::bitcoin::{
+        Amount, ScriptBuf, Transaction, TxIn, Txid, absolute::LockTime, bip158::BlockFilter,
+        hashes::Hash, transaction::Version,
+    };
+    use zeromq::{PubSocket, Socket, SocketSend, ZmqMessage};
 
     use super::*;
+    use crate::store::model::{BlockEvent, MempoolEvent};
+    use crate::tests::bip352::receiving_cases;
+    use crate::tests::fixtures::{ClientMock, chain, extend_chain, memory_store};
+
+    fn syncer_config() -> SyncerConfig {
+        SyncerConfig {
+            rpc_url: String::new(),
+            rpc_user: String::new(),
+            rpc_pass: String::new(),
+            sync_from: 0,
+            cache_size: 16,
+            blocks_dir: None,
+            parallelism: 1,
+            zmq_block_url: None,
+        }
+    }
+
+    fn event_summary(event: BlockEvent) -> (bool, i64) {
+        match event {
+            BlockEvent::Connected(block) => (true, block.height),
+            BlockEvent::Disconnected(block) => (false, block.height),
+        }
+    }
 
     #[test]
     fn test_prevout_cache() {
@@ -278,7 +792,7 @@ mod tests {
         let mut cache = PrevoutCache::new(5);
 
         for op in outpoints.iter() {
-            assert_eq!(cache.get(&op), None);
+            assert_eq!(cache.get(op), None);
         }
 
         cache.insert(outpoints[0], txouts[0].clone());
@@ -302,12 +816,420 @@ mod tests {
 
         assert!(cache.map.len() <= cache.size);
 
-        for i in 0..5 {
-            assert_eq!(cache.get(&outpoints[i]), None);
+        for outpoint in outpoints.iter().take(5) {
+            assert_eq!(cache.get(outpoint), None);
+        }
+
+        for (outpoint, txout) in outpoints.iter().zip(txouts.iter()).skip(5) {
+            assert_eq!(cache.get(outpoint), Some(txout));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reorg_to_longer_chain() {
+        let store = memory_store().await;
+        let mut blocks = chain(5);
+
+        let client = ClientMock::new(blocks.clone(), vec![]);
+        let mut syncer = Syncer::new(syncer_config(), client, store.clone());
+        assert_eq!(syncer.sync_to_tip(0).await.unwrap(), 5);
+
+        let mut rx = store.subscribe_blocks();
+
+        // Replace blocks 4 and 5 with a branch of three blocks.
+        blocks.truncate(4);
+        extend_chain(&mut blocks, 3, 1);
+        let client = ClientMock::new(blocks.clone(), vec![]);
+        let mut syncer = Syncer::new(syncer_config(), client, store.clone());
+        assert_eq!(syncer.sync_to_tip(5).await.unwrap(), 6);
+
+        for (height, block) in blocks.iter().enumerate().skip(1) {
+            let stored_hash = store.get_block_hash(height as i64).await.unwrap();
+            assert_eq!(stored_hash, Some(block.block_hash().to_string()));
+        }
+
+        let mut events = vec![];
+        while let Ok(event) = rx.try_recv() {
+            events.push(event_summary(event));
+        }
+        assert_eq!(
+            events,
+            vec![(false, 5), (false, 4), (true, 4), (true, 5), (true, 6)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reorg_to_shorter_chain() {
+        let store = memory_store().await;
+        let mut blocks = chain(5);
+
+        let client = ClientMock::new(blocks.clone(), vec![]);
+        let mut syncer = Syncer::new(syncer_config(), client, store.clone());
+        assert_eq!(syncer.sync_to_tip(0).await.unwrap(), 5);
+
+        blocks.truncate(3);
+        let client = ClientMock::new(blocks.clone(), vec![]);
+        let mut syncer = Syncer::new(syncer_config(), client, store.clone());
+        assert_eq!(syncer.sync_to_tip(5).await.unwrap(), 2);
+
+        assert_eq!(store.get_synced_blocks_height().await.unwrap(), Some(2));
+        assert_eq!(store.get_block_hash(3).await.unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_parallel_sync() {
+        let (simple_prev, simple_tx) = vector_transaction("Simple send: two inputs");
+        let mut blocks = chain(10);
+        blocks[3].txdata.push(simple_tx);
+        let txs = vec![simple_prev];
+
+        let sequential_store = memory_store().await;
+        let mut sequential_rx = sequential_store.subscribe_blocks();
+        let client = ClientMock::new(blocks.clone(), txs.clone());
+        let mut syncer = Syncer::new(syncer_config(), client, sequential_store.clone());
+        assert_eq!(syncer.sync_to_tip(0).await.unwrap(), 10);
+
+        let parallel_store = memory_store().await;
+        let mut parallel_rx = parallel_store.subscribe_blocks();
+        let client = ClientMock::new(blocks.clone(), txs);
+        let mut syncer = Syncer::new(
+            SyncerConfig {
+                parallelism: 4,
+                ..syncer_config()
+            },
+            client,
+            parallel_store.clone(),
+        );
+        assert_eq!(syncer.sync_to_tip(0).await.unwrap(), 10);
+
+        for height in 1..=10 {
+            let Ok(BlockEvent::Connected(sequential)) = sequential_rx.try_recv() else {
+                panic!("expected connected block {height}");
+            };
+            let Ok(BlockEvent::Connected(parallel)) = parallel_rx.try_recv() else {
+                panic!("expected connected block {height}");
+            };
+            assert_eq!(parallel.height, height);
+            assert_eq!(parallel.hash, sequential.hash);
+            let scalars = |block: model::Block| -> Vec<String> {
+                block.transactions.into_iter().map(|tx| tx.scalar).collect()
+            };
+            assert_eq!(scalars(parallel), scalars(sequential));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_parallel_sync_reorg() {
+        let store = memory_store().await;
+        let mut blocks = chain(5);
+
+        let client = ClientMock::new(blocks.clone(), vec![]);
+        let mut syncer = Syncer::new(syncer_config(), client, store.clone());
+        assert_eq!(syncer.sync_to_tip(0).await.unwrap(), 5);
+
+        // Replace blocks 4 and 5 with a branch of six blocks, the first block fetched in parallel
+        // does not build on the stored tip.
+        blocks.truncate(4);
+        extend_chain(&mut blocks, 6, 1);
+        let client = ClientMock::new(blocks.clone(), vec![]);
+        let cfg = SyncerConfig {
+            parallelism: 3,
+            ..syncer_config()
+        };
+        let mut syncer = Syncer::new(cfg, client, store.clone());
+        assert_eq!(syncer.sync_to_tip(5).await.unwrap(), 9);
+
+        for (height, block) in blocks.iter().enumerate().skip(1) {
+            let stored_hash = store.get_block_hash(height as i64).await.unwrap();
+            assert_eq!(stored_hash, Some(block.block_hash().to_string()));
         }
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_block_notification() {
+        let mut publisher = PubSocket::new();
+        let endpoint = publisher.bind("tcp://127.0.0.1:0").await.unwrap();
+
+        let client = ClientMock::new(chain(1), vec![]);
+        let mut syncer = Syncer::new(syncer_config(), client, memory_store().await);
+        syncer.block_notifications = Some(
+            BlockNotifications::connect(&endpoint.to_string())
+                .await
+                .unwrap(),
+        );
+
+        let notify = async {
+            loop {
+                let mut message = ZmqMessage::from("hashblock");
+                message.push_back(vec![0u8; 32].into());
+                message.push_back(vec![0u8; 4].into());
+                publisher.send(message).await.unwrap();
+                sleep(Duration::from_millis(50)).await;
+            }
+        };
+        // Returns well before the fallback poll interval.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::select! {
+                _ = syncer.wait_for_block() => {}
+                _ = notify => unreachable!(),
+            }
+        })
+        .await
+        .unwrap();
+        assert!(syncer.block_notifications.is_some());
+    }
+
+    // Build a transaction spending the inputs of a BIP-352 receiving test case to its outputs. The
+    // outputs spent are created by the returned previous transaction.
+    fn vector_transaction(comment: &str) -> (Transaction, Transaction) {
+        let case = receiving_cases()
+            .into_iter()
+            .find(|case| case.comment == comment)
+            .unwrap();
+        let (inputs, prevouts) = case.inputs();
+
+        let previous_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: prevouts,
+        };
+        let input = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(vout, txin)| TxIn {
+                previous_output: OutPoint::new(previous_tx.compute_txid(), vout as u32),
+                ..txin
+            })
+            .collect();
+        let output = case.given["outputs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|output| TxOut {
+                value: Amount::from_sat(1000),
+                script_pubkey: ScriptBuf::from_hex(&format!("5120{}", output.as_str().unwrap()))
+                    .unwrap(),
+            })
+            .collect();
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input,
+            output,
+        };
+        (previous_tx, tx)
+    }
+
+    #[test]
+    fn test_get_cached_prevout() {
+        // The mock knows no transactions, prevouts can only come from the cache.
+        let client = ClientMock::new(chain(0), vec![]);
+        let cache = Mutex::new(PrevoutCache::new(5));
+        let txid =
+            Txid::from_str("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+                .unwrap();
+        let txout = TxOut {
+            value: Amount::from_sat(1),
+            script_pubkey: ScriptBuf::new(),
+        };
+        let cached = OutPoint::new(txid, 0);
+        let missing_vout = OutPoint::new(txid, 1);
+        cache.lock().unwrap().insert(cached, vec![txout.clone()]);
+        cache
+            .lock()
+            .unwrap()
+            .insert(missing_vout, vec![txout.clone()]);
+
+        assert_eq!(get_cached_prevout(&client, &cache, &cached).unwrap(), txout);
+        assert!(matches!(
+            get_cached_prevout(&client, &cache, &missing_vout),
+            Err(Error::InvalidInput)
+        ));
+        assert!(get_cached_prevout(&client, &cache, &OutPoint::new(txid, 2)).is_err());
+    }
+
+    // Index a block with prevouts from the block or, without `block_prevouts`, the prevout cache.
+    fn index_test_block<C: BitcionRpc + Send + Sync + 'static>(
+        syncer: &Syncer<C>,
+        block_prevouts: bool,
+        height: u64,
+    ) -> (BlockHash, model::Block) {
+        let block_prevouts = AtomicBool::new(block_prevouts);
+        fetch_and_index_block(
+            syncer.client.as_ref(),
+            &syncer.prevout_cache,
+            &block_prevouts,
+            height,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_process_block_skips_input_keys_summing_to_infinity() {
+        let (infinity_prev, infinity_tx) = vector_transaction(
+            "Input keys sum up to zero / point at infinity: sending fails, receiver skips tx",
+        );
+        let (simple_prev, simple_tx) = vector_transaction("Simple send: two inputs");
+
+        let mut blocks = chain(1);
+        blocks[1].txdata.push(infinity_tx);
+        blocks[1].txdata.push(simple_tx.clone());
+
+        let client = ClientMock::new(blocks.clone(), vec![infinity_prev, simple_prev]);
+        let syncer = Syncer::new(syncer_config(), client, memory_store().await);
+        let (_, block) = index_test_block(&syncer, false, 1);
+
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(
+            block.transactions[0].txid,
+            simple_tx.compute_txid().to_string()
+        );
+    }
 
-        for i in 5..10 {
-            assert_eq!(cache.get(&outpoints[i]), Some(&txouts[i]));
+    #[tokio::test]
+    async fn test_process_block_with_block_prevouts() {
+        let (simple_prev, simple_tx) = vector_transaction("Simple send: two inputs");
+        let (nums_prev, nums_tx) =
+            vector_transaction("Single recipient: taproot input with NUMS point");
+
+        let mut blocks = chain(1);
+        blocks[1].txdata.push(simple_tx);
+        blocks[1].txdata.push(nums_tx);
+
+        let client = ClientMock::new(blocks.clone(), vec![simple_prev, nums_prev]);
+        let syncer = Syncer::new(syncer_config(), client, memory_store().await);
+
+        let (_, with_block_prevouts) = index_test_block(&syncer, true, 1);
+        let (_, with_prevout_cache) = index_test_block(&syncer, false, 1);
+
+        let scalars = |block: model::Block| -> Vec<String> {
+            block.transactions.into_iter().map(|tx| tx.scalar).collect()
+        };
+        assert_eq!(with_block_prevouts.transactions.len(), 2);
+        assert_eq!(scalars(with_block_prevouts), scalars(with_prevout_cache));
+    }
+
+    #[tokio::test]
+    async fn test_sync_mempool() {
+        let (simple_prev, simple_tx) = vector_transaction("Simple send: two inputs");
+        let (infinity_prev, infinity_tx) = vector_transaction(
+            "Input keys sum up to zero / point at infinity: sending fails, receiver skips tx",
+        );
+        let mempool = vec![simple_tx.compute_txid(), infinity_tx.compute_txid()];
+        let txs = vec![simple_prev, simple_tx.clone(), infinity_prev, infinity_tx];
+
+        let store = memory_store().await;
+        let mut rx = store.subscribe_mempool();
+
+        let client = ClientMock::new(chain(1), txs.clone()).with_mempool(mempool);
+        let mut mempool = Syncer::new(syncer_config(), client, store.clone()).mempool_syncer();
+        assert!(!mempool.sync().await.unwrap());
+
+        let transactions = store.get_mempool_transactions().await.unwrap().transactions;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].txid, simple_tx.compute_txid().to_string());
+        assert!(matches!(rx.try_recv(), Ok(MempoolEvent::Added(txs)) if txs.len() == 1));
+
+        // The transaction left the mempool, e.g. because it was confirmed.
+        let client = ClientMock::new(chain(1), txs);
+        let mut mempool = Syncer::new(syncer_config(), client, store.clone()).mempool_syncer();
+        mempool.sync().await.unwrap();
+
+        let transactions = store.get_mempool_transactions().await.unwrap().transactions;
+        assert!(transactions.is_empty());
+        assert!(matches!(rx.try_recv(), Ok(MempoolEvent::Removed(txids)) if txids.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn test_sync_mempool_retries_failed_transactions() {
+        let (simple_prev, simple_tx) = vector_transaction("Simple send: two inputs");
+        let txids = vec![simple_tx.compute_txid()];
+
+        // The spent outputs can not be looked up yet.
+        let store = memory_store().await;
+        let client = ClientMock::new(chain(1), vec![simple_tx.clone()]).with_mempool(txids.clone());
+        let mut mempool = Syncer::new(syncer_config(), client, store.clone()).mempool_syncer();
+        mempool.sync().await.unwrap();
+        assert!(
+            store
+                .get_mempool_transactions()
+                .await
+                .unwrap()
+                .transactions
+                .is_empty()
+        );
+        assert!(mempool.seen.is_empty());
+
+        mempool.client = Arc::new(
+            ClientMock::new(chain(1), vec![simple_prev, simple_tx.clone()]).with_mempool(txids),
+        );
+        mempool.sync().await.unwrap();
+        let transactions = store.get_mempool_transactions().await.unwrap().transactions;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].txid, simple_tx.compute_txid().to_string());
+        assert!(mempool.seen.contains(&simple_tx.compute_txid()));
+    }
+
+    #[tokio::test]
+    async fn test_backfill() {
+        let (prev, tx) = vector_transaction("Simple send: two inputs");
+        let mut spending_tx = tx.clone();
+        spending_tx.input.truncate(1);
+        spending_tx.input[0].previous_output = OutPoint::new(tx.compute_txid(), 0);
+        let mut blocks = chain(3);
+        blocks[1].txdata.push(tx.clone());
+        blocks[2].txdata.push(spending_tx.clone());
+
+        // Blocks were indexed before spends were recorded.
+        let store = memory_store().await;
+        let client = ClientMock::new(blocks.clone(), vec![prev, tx.clone()]);
+        let mut syncer = Syncer::new(syncer_config(), client, store.clone());
+        for height in 1..blocks.len() {
+            let (_, mut block) = index_test_block(&syncer, false, height as u64);
+            block.spent.clear();
+            block.filters.clear();
+            store.add_block(block).await.unwrap();
         }
+        store.set_backfill_height(4).await;
+        let filter = |height| store.get_filter(height, model::FilterKind::NewUtxos);
+        let spent_filter = |height| store.get_filter(height, model::FilterKind::Spent);
+        assert!(filter(1).await.unwrap().is_none());
+        assert!(spent_filter(2).await.unwrap().is_none());
+
+        assert!(!syncer.backfill().await.unwrap());
+        assert_eq!(store.get_backfill_height().await.unwrap(), 1);
+        let spent = store
+            .get_output(tx.compute_txid().to_string(), 0)
+            .await
+            .unwrap()
+            .unwrap()
+            .spent
+            .unwrap();
+        assert_eq!(spent.txid, spending_tx.compute_txid().to_string());
+        assert_eq!(spent.height, 2);
+
+        // The filter is built from the stored transactions of the block.
+        let filter = filter(1).await.unwrap().unwrap();
+        let filter = BlockFilter::new(&hex::decode(filter.filter).unwrap());
+        let output_key = &tx.output[0].script_pubkey.as_bytes()[2..];
+        assert!(
+            filter
+                .match_any(&blocks[1].block_hash(), [output_key].into_iter())
+                .unwrap()
+        );
+
+        let spent_filter = spent_filter(2).await.unwrap().unwrap();
+        let spent_filter = BlockFilter::new(&hex::decode(spent_filter.filter).unwrap());
+        let block_hash = blocks[2].block_hash();
+        let element = filter::spent_outpoint_hash(
+            &OutPoint::new(tx.compute_txid(), 0),
+            block_hash.as_byte_array(),
+        );
+        assert!(
+            spent_filter
+                .match_any(&block_hash, [element.as_slice()].into_iter())
+                .unwrap()
+        );
     }
 }
@@ -1,14 +1,120 @@
 use bitcoincore_rpc::{
     Client, RpcApi,
-    bitcoin::{Block, Transaction, Txid},
+    bitcoin::{
+        Amount, Block, BlockHash, CompactTarget, OutPoint, ScriptBuf, Transaction, TxMerkleNode,
+        TxOut, Txid, block, consensus, hashes::Hash,
+    },
+    jsonrpc,
 };
+use serde::Deserialize;
 
 use crate::Result;
 
+// Block together with the outputs spent by its transactions. `prevouts[i][j]` is the output spent
+// by input `j` of transaction `i`, the coinbase transaction has no prevouts.
+#[derive(Debug, Clone)]
+pub struct BlockWithPrevouts {
+    pub block: Block,
+    pub prevouts: Vec<Vec<TxOut>>,
+}
+
 pub trait BitcionRpc {
     fn get_block_by_height(&self, height: u64) -> Result<Block>;
+    fn get_block_hash_by_height(&self, height: u64) -> Result<BlockHash>;
+    // Returns `None` if the node can not return prevouts together with the block.
+    fn get_block_with_prevouts(&self, height: u64) -> Result<Option<BlockWithPrevouts>>;
     fn get_chain_tip(&self) -> Result<usize>;
     fn get_transaction(&self, txid: &Txid) -> Result<Transaction>;
+    fn get_mempool_txids(&self) -> Result<Vec<Txid>>;
+    // Returns the output if it is in the UTXO set of the chain tip, ignoring the mempool.
+    fn get_unspent_output(&self, outpoint: &OutPoint) -> Result<Option<TxOut>>;
+}
+
+// `getblock <hash> 3` response, only the fields we need to rebuild the block with its prevouts.
+#[derive(Deserialize)]
+struct VerboseBlock {
+    hash: BlockHash,
+    version: i32,
+    previousblockhash: Option<BlockHash>,
+    merkleroot: TxMerkleNode,
+    time: u32,
+    bits: String,
+    nonce: u32,
+    tx: Vec<VerboseTransaction>,
+}
+
+#[derive(Deserialize)]
+struct VerboseTransaction {
+    hex: String,
+    vin: Vec<VerboseInput>,
+}
+
+#[derive(Deserialize)]
+struct VerboseInput {
+    coinbase: Option<String>,
+    // Only present for verbosity 3 (Bitcoin Core >= 25.0).
+    prevout: Option<VerbosePrevout>,
+}
+
+#[derive(Deserialize)]
+struct VerbosePrevout {
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    value: Amount,
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: VerboseScriptPubKey,
+}
+
+#[derive(Deserialize)]
+struct VerboseScriptPubKey {
+    hex: String,
+}
+
+impl VerboseBlock {
+    fn into_block_with_prevouts(self) -> Result<Option<BlockWithPrevouts>> {
+        let bits = u32::from_str_radix(&self.bits, 16)
+            .map_err(|_| bitcoincore_rpc::Error::UnexpectedStructure)?;
+        let header = block::Header {
+            version: block::Version::from_consensus(self.version),
+            prev_blockhash: self.previousblockhash.unwrap_or(BlockHash::all_zeros()),
+            merkle_root: self.merkleroot,
+            time: self.time,
+            bits: CompactTarget::from_consensus(bits),
+            nonce: self.nonce,
+        };
+        if header.block_hash() != self.hash {
+            return Err(bitcoincore_rpc::Error::UnexpectedStructure.into());
+        }
+
+        let mut txdata = Vec::with_capacity(self.tx.len());
+        let mut prevouts = Vec::with_capacity(self.tx.len());
+        for tx in self.tx {
+            let mut tx_prevouts = vec![];
+            for vin in tx.vin {
+                if vin.coinbase.is_some() {
+                    continue;
+                }
+                // Older nodes treat verbosity 3 like verbosity 2.
+                let Some(prevout) = vin.prevout else {
+                    return Ok(None);
+                };
+                let script_pubkey = ScriptBuf::from_hex(&prevout.script_pub_key.hex)
+                    .map_err(|_| bitcoincore_rpc::Error::UnexpectedStructure)?;
+                tx_prevouts.push(TxOut {
+                    value: prevout.value,
+                    script_pubkey,
+                });
+            }
+            let tx: Transaction = consensus::encode::deserialize_hex(&tx.hex)
+                .map_err(bitcoincore_rpc::Error::BitcoinSerialization)?;
+            txdata.push(tx);
+            prevouts.push(tx_prevouts);
+        }
+
+        Ok(Some(BlockWithPrevouts {
+            block: Block { header, txdata },
+            prevouts,
+        }))
+    }
 }
 
 impl BitcionRpc for Client {
@@ -17,6 +123,25 @@ impl BitcionRpc for Client {
         Ok(self.get_block(&block_hash)?)
     }
 
+    fn get_block_hash_by_height(&self, height: u64) -> Result<BlockHash> {
+        Ok(self.get_block_hash(height)?)
+    }
+
+    fn get_block_with_prevouts(&self, height: u64) -> Result<Option<BlockWithPrevouts>> {
+        let block_hash = self.get_block_hash(height)?;
+        let verbose_block: VerboseBlock = match self
+            .call("getblock", &[block_hash.to_string().into(), 3.into()])
+        {
+            Ok(verbose_block) => verbose_block,
+            // Invalid parameter, the node does not know verbosity 3.
+            Err(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(err))) if err.code == -8 => {
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        verbose_block.into_block_with_prevouts()
+    }
+
     fn get_chain_tip(&self) -> Result<usize> {
         let best_block_hash = self.get_best_block_hash()?;
         let best_block_info = self.get_block_info(&best_block_hash)?;
@@ -26,4 +151,82 @@ impl BitcionRpc for Client {
     fn get_transaction(&self, txid: &Txid) -> Result<Transaction> {
         Ok(self.get_raw_transaction(txid, None)?)
     }
+
+    fn get_mempool_txids(&self) -> Result<Vec<Txid>> {
+        Ok(self.get_raw_mempool()?)
+    }
+
+    fn get_unspent_output(&self, outpoint: &OutPoint) -> Result<Option<TxOut>> {
+        let txout = self.get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?;
+        Ok(txout.map(|txout| TxOut {
+            value: txout.value,
+            script_pubkey: ScriptBuf::from_bytes(txout.script_pub_key.hex),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoincore_rpc::bitcoin::{Network, constants::genesis_block};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    // `getblock <hash> 3` response for the regtest genesis block with an additional transaction
+    // spending the genesis coinbase output.
+    fn verbose_block_json(with_prevouts: bool) -> (Block, Value) {
+        let mut block = genesis_block(Network::Regtest);
+        let mut spending_tx = block.txdata[0].clone();
+        spending_tx.input[0].previous_output.txid = block.txdata[0].compute_txid();
+        block.txdata.push(spending_tx);
+
+        let coinbase = &block.txdata[0];
+        let prevout = json!({
+            "value": coinbase.output[0].value.to_btc(),
+            "scriptPubKey": { "hex": coinbase.output[0].script_pubkey.to_hex_string() },
+        });
+        let spending_vin = match with_prevouts {
+            true => json!({ "txid": coinbase.compute_txid(), "vout": 0, "prevout": prevout }),
+            false => json!({ "txid": coinbase.compute_txid(), "vout": 0 }),
+        };
+        let verbose_block = json!({
+            "hash": block.block_hash(),
+            "version": block.header.version.to_consensus(),
+            "merkleroot": block.header.merkle_root,
+            "time": block.header.time,
+            "bits": format!("{:08x}", block.header.bits.to_consensus()),
+            "nonce": block.header.nonce,
+            "tx": [
+                {
+                    "hex": consensus::encode::serialize_hex(&block.txdata[0]),
+                    "vin": [{ "coinbase": "04ffff001d" }],
+                },
+                {
+                    "hex": consensus::encode::serialize_hex(&block.txdata[1]),
+                    "vin": [spending_vin],
+                },
+            ],
+        });
+        (block, verbose_block)
+    }
+
+    #[test]
+    fn test_verbose_block_with_prevouts() {
+        let (block, verbose_block) = verbose_block_json(true);
+        let verbose_block: VerboseBlock = serde_json::from_value(verbose_block).unwrap();
+        let block_with_prevouts = verbose_block.into_block_with_prevouts().unwrap().unwrap();
+
+        assert_eq!(block_with_prevouts.block, block);
+        assert_eq!(
+            block_with_prevouts.prevouts,
+            vec![vec![], vec![block.txdata[0].output[0].clone()]]
+        );
+    }
+
+    #[test]
+    fn test_verbose_block_without_prevouts() {
+        let (_, verbose_block) = verbose_block_json(false);
+        let verbose_block: VerboseBlock = serde_json::from_value(verbose_block).unwrap();
+        assert!(verbose_block.into_block_with_prevouts().unwrap().is_none());
+    }
 }
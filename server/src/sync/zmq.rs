@@ -0,0 +1,101 @@
+use bitcoincore_rpc::bitcoin::{BlockHash, hashes::Hash};
+use tracing::{info, warn};
+use zeromq::{Socket, SocketRecv, SubSocket};
+
+use crate::Result;
+
+const HASHBLOCK_TOPIC: &str = "hashblock";
+
+// Subscriber to the node's `zmqpubhashblock` notifications.
+pub struct BlockNotifications {
+    socket: SubSocket,
+}
+
+impl BlockNotifications {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let mut socket = SubSocket::new();
+        socket.connect(url).await?;
+        socket.subscribe(HASHBLOCK_TOPIC).await?;
+        info!("Subscribed to block notifications at {}", url);
+        Ok(Self { socket })
+    }
+
+    // Wait for the next block notification and return the hash of the new block.
+    pub async fn recv(&mut self) -> Result<BlockHash> {
+        loop {
+            // Messages consist of the topic, the block hash and a sequence number.
+            let message = self.socket.recv().await?;
+            let hash = match (message.get(0), message.get(1)) {
+                (Some(topic), Some(hash)) if topic.as_ref() == HASHBLOCK_TOPIC.as_bytes() => hash,
+                _ => {
+                    warn!(
+                        "Ignoring unexpected ZMQ message with {} frames",
+                        message.len()
+                    );
+                    continue;
+                }
+            };
+            let Ok(mut bytes) = <[u8; 32]>::try_from(hash.as_ref()) else {
+                warn!(
+                    "Ignoring hashblock message with invalid hash length {}",
+                    hash.len()
+                );
+                continue;
+            };
+            // The hash is published in the byte order it is displayed in.
+            bytes.reverse();
+            return Ok(BlockHash::from_byte_array(bytes));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Duration};
+
+    use zeromq::{PubSocket, SocketSend, ZmqMessage};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recv_hashblock() {
+        let hash =
+            BlockHash::from_str("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206")
+                .unwrap();
+
+        let mut publisher = PubSocket::new();
+        let endpoint = publisher.bind("tcp://127.0.0.1:0").await.unwrap();
+        let mut notifications = BlockNotifications::connect(&endpoint.to_string())
+            .await
+            .unwrap();
+
+        let mut hash_frame = hash.to_byte_array();
+        hash_frame.reverse();
+        let send = async {
+            // Messages published before the subscription reached the publisher are dropped, so
+            // keep publishing until one is received.
+            loop {
+                let mut message = ZmqMessage::from("rawtx");
+                message.push_back(vec![0u8; 32].into());
+                publisher.send(message).await.unwrap();
+
+                let mut message = ZmqMessage::from(HASHBLOCK_TOPIC);
+                message.push_back(hash_frame.to_vec().into());
+                message.push_back(0u32.to_le_bytes().to_vec().into());
+                publisher.send(message).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::select! {
+                hash = notifications.recv() => hash,
+                _ = send => unreachable!(),
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(received, hash);
+    }
+}
@@ -0,0 +1,90 @@
+// BIP-352 test vectors from
+// https://github.com/bitcoin/bips/blob/master/bip-0352/send_and_receive_test_vectors.json
+use std::str::FromStr;
+
+use bitcoincore_rpc::bitcoin::{
+    Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Txid, Witness, consensus,
+};
+use serde_json::Value;
+
+use crate::{Error, TweakError, compute_tweak};
+
+const TEST_VECTORS: &str = include_str!("data/send_and_receive_test_vectors.json");
+
+pub struct ReceivingCase {
+    pub comment: String,
+    pub given: Value,
+    pub expected: Value,
+}
+
+impl ReceivingCase {
+    pub fn inputs(&self) -> (Vec<TxIn>, Vec<TxOut>) {
+        self.given["vin"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|vin| {
+                let txid = Txid::from_str(vin["txid"].as_str().unwrap()).unwrap();
+                let vout = vin["vout"].as_u64().unwrap() as u32;
+                let witness_bytes = hex::decode(vin["txinwitness"].as_str().unwrap()).unwrap();
+                let witness = match witness_bytes.is_empty() {
+                    true => Witness::default(),
+                    false => consensus::deserialize::<Witness>(&witness_bytes).unwrap(),
+                };
+                let txin = TxIn {
+                    previous_output: OutPoint::new(txid, vout),
+                    script_sig: script_from_hex(vin["scriptSig"].as_str().unwrap()),
+                    sequence: Sequence::MAX,
+                    witness,
+                };
+                let prevout = TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: script_from_hex(
+                        vin["prevout"]["scriptPubKey"]["hex"].as_str().unwrap(),
+                    ),
+                };
+                (txin, prevout)
+            })
+            .unzip()
+    }
+}
+
+fn script_from_hex(script_hex: &str) -> ScriptBuf {
+    ScriptBuf::from_bytes(hex::decode(script_hex).unwrap())
+}
+
+pub fn receiving_cases() -> Vec<ReceivingCase> {
+    let vectors: Value = serde_json::from_str(TEST_VECTORS).unwrap();
+    vectors
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|vector| {
+            let comment = vector["comment"].as_str().unwrap().to_string();
+            vector["receiving"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(move |receiving| ReceivingCase {
+                    comment: comment.clone(),
+                    given: receiving["given"].clone(),
+                    expected: receiving["expected"].clone(),
+                })
+        })
+        .collect()
+}
+
+#[test]
+fn test_tweak_vectors() {
+    for case in receiving_cases() {
+        let (inputs, prevouts) = case.inputs();
+        // Transactions the receiver has to skip have no tweak.
+        let tweak = match compute_tweak(&inputs, &prevouts) {
+            Ok(tweak) => tweak.map(|tweak| hex::encode(tweak.serialize())),
+            Err(Error::Tweak(TweakError::InputKeySumIsInfinity)) => None,
+            Err(err) => panic!("{}: {:?}", case.comment, err),
+        };
+        let expected = case.expected["tweak"].as_str().map(String::from);
+        assert_eq!(tweak, expected, "{}", case.comment);
+    }
+}
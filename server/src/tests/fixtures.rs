@@ -1,13 +1,35 @@
 use std::collections::HashMap;
 
-use bitcoincore_rpc::bitcoin::{Block, Transaction, Txid};
+use bitcoincore_rpc::bitcoin::{
+    Block, BlockHash, Network, OutPoint, Transaction, TxOut, Txid, constants::genesis_block,
+};
 
-use crate::sync::BitcionRpc;
+use crate::config::DatabaseConfig;
+use crate::store::Store;
+use crate::sync::{BitcionRpc, BlockWithPrevouts};
 
 pub struct ClientMock {
     height: usize,
     blocks: Vec<Block>,
     txs: HashMap<Txid, Transaction>,
+    mempool: Vec<Txid>,
+}
+
+impl ClientMock {
+    pub fn new(blocks: Vec<Block>, txs: Vec<Transaction>) -> Self {
+        Self {
+            height: blocks.len() - 1,
+            blocks,
+            txs: txs.into_iter().map(|tx| (tx.compute_txid(), tx)).collect(),
+            mempool: vec![],
+        }
+    }
+
+    // Put transactions into the mempool, they must be known to the mock.
+    pub fn with_mempool(mut self, txids: Vec<Txid>) -> Self {
+        self.mempool = txids;
+        self
+    }
 }
 
 impl BitcionRpc for ClientMock {
@@ -17,6 +39,32 @@ impl BitcionRpc for ClientMock {
         )?)
     }
 
+    fn get_block_hash_by_height(&self, height: u64) -> crate::Result<BlockHash> {
+        Ok(self.get_block_by_height(height)?.block_hash())
+    }
+
+    fn get_block_with_prevouts(&self, height: u64) -> crate::Result<Option<BlockWithPrevouts>> {
+        let block = self.get_block_by_height(height)?;
+        let prevouts = block
+            .txdata
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .map(|tx| {
+                tx.input
+                    .iter()
+                    .map(|txin| {
+                        let outpoint = txin.previous_output;
+                        let tx = self.get_transaction(&outpoint.txid)?;
+                        Ok(tx.output[outpoint.vout as usize].clone())
+                    })
+                    .collect::<crate::Result<Vec<TxOut>>>()
+            })
+            .collect::<crate::Result<Vec<Vec<TxOut>>>>()?;
+        // The coinbase transaction has no prevouts.
+        let prevouts = std::iter::once(vec![]).chain(prevouts).collect();
+        Ok(Some(BlockWithPrevouts { block, prevouts }))
+    }
+
     fn get_chain_tip(&self) -> crate::Result<usize> {
         Ok(self.height)
     }
@@ -28,4 +76,46 @@ impl BitcionRpc for ClientMock {
             .cloned()
             .ok_or(bitcoincore_rpc::Error::ReturnedError("invalid txid".into()))?)
     }
+
+    fn get_mempool_txids(&self) -> crate::Result<Vec<Txid>> {
+        Ok(self.mempool.clone())
+    }
+
+    // Outputs of known transactions that are not in the mempool are considered unspent.
+    fn get_unspent_output(&self, outpoint: &OutPoint) -> crate::Result<Option<TxOut>> {
+        if self.mempool.contains(&outpoint.txid) {
+            return Ok(None);
+        }
+        Ok(self
+            .txs
+            .get(&outpoint.txid)
+            .and_then(|tx| tx.output.get(outpoint.vout as usize))
+            .cloned())
+    }
+}
+
+// Regtest chain of `length` blocks on top of the genesis block. All blocks only contain the
+// genesis coinbase.
+pub fn chain(length: usize) -> Vec<Block> {
+    let mut blocks = vec![genesis_block(Network::Regtest)];
+    extend_chain(&mut blocks, length, 0);
+    blocks
+}
+
+// Append `length` blocks to `blocks`. Blocks appended with a different `branch` get different
+// hashes, so truncating a chain and extending it with a new branch simulates a reorg.
+pub fn extend_chain(blocks: &mut Vec<Block>, length: usize, branch: u32) {
+    for _ in 0..length {
+        let mut block = blocks.last().unwrap().clone();
+        block.header.prev_blockhash = block.block_hash();
+        block.header.nonce = branch;
+        blocks.push(block);
+    }
+}
+
+pub async fn memory_store() -> Store {
+    let cfg = DatabaseConfig {
+        database_url: "sqlite::memory:".into(),
+    };
+    Store::new(cfg).await.unwrap()
 }
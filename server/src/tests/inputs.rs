@@ -0,0 +1,135 @@
+use bitcoin::hashes::{Hash, hash160};
+use bitcoincore_rpc::bitcoin::{
+    Amount, OutPoint, PubkeyHash, ScriptBuf, Sequence, TxIn, TxOut, Witness,
+    script::{Builder, PushBytesBuf},
+};
+use secp256k1::{Parity, PublicKey, Secp256k1, SecretKey};
+
+use crate::try_get_input_public_key;
+
+fn p2pkh_input(script_sig: ScriptBuf, key_bytes: &[u8]) -> (TxIn, TxOut) {
+    let txin = TxIn {
+        previous_output: OutPoint::null(),
+        script_sig,
+        sequence: Sequence::MAX,
+        witness: Witness::default(),
+    };
+    let public_key_hash = PubkeyHash::from_raw_hash(hash160::Hash::hash(key_bytes));
+    let prevout = TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::new_p2pkh(&public_key_hash),
+    };
+    (txin, prevout)
+}
+
+fn push(builder: Builder, data: &[u8]) -> Builder {
+    builder.push_slice(PushBytesBuf::try_from(data.to_vec()).unwrap())
+}
+
+#[test]
+fn test_p2pkh_uncompressed_key_is_skipped() {
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_slice(&[0x01; 32]).unwrap();
+    let uncompressed = secret_key.public_key(&secp).serialize_uncompressed();
+
+    let script_sig = push(Builder::new(), &[0x30; 71]);
+    let script_sig = push(script_sig, &uncompressed).into_script();
+    let (txin, prevout) = p2pkh_input(script_sig, &uncompressed);
+
+    assert!(try_get_input_public_key(&txin, &prevout).is_err());
+}
+
+#[test]
+fn test_p2pkh_invalid_point_does_not_panic() {
+    // Hashes to the public key hash but is not a valid public key.
+    let not_a_point = [0x05; 33];
+
+    let script_sig = push(Builder::new(), &[0x30; 71]);
+    let script_sig = push(script_sig, &not_a_point).into_script();
+    let (txin, prevout) = p2pkh_input(script_sig, &not_a_point);
+
+    assert!(try_get_input_public_key(&txin, &prevout).is_err());
+}
+
+#[test]
+fn test_p2pkh_scans_from_end() {
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_slice(&[0x01; 32]).unwrap();
+    let public_key = secret_key.public_key(&secp);
+    let other_public_key = SecretKey::from_slice(&[0x02; 32])
+        .unwrap()
+        .public_key(&secp);
+
+    // Malleated scriptSig pushing another key in front of the signature and public key.
+    let script_sig = push(Builder::new(), &other_public_key.serialize());
+    let script_sig = push(script_sig, &[0x30; 71]);
+    let script_sig = push(script_sig, &public_key.serialize()).into_script();
+    let (txin, prevout) = p2pkh_input(script_sig, &public_key.serialize());
+
+    assert_eq!(
+        try_get_input_public_key(&txin, &prevout).unwrap(),
+        public_key
+    );
+}
+
+#[test]
+fn test_p2sh_empty_script_sig_does_not_panic() {
+    let txin = TxIn {
+        previous_output: OutPoint::null(),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::default(),
+    };
+    let prevout = TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::from_hex("a914000000000000000000000000000000000000000087")
+            .unwrap(),
+    };
+
+    assert!(try_get_input_public_key(&txin, &prevout).is_err());
+}
+
+// Script path spend of `output_key` with `internal_key` in the control block.
+fn p2tr_script_path_input(output_key: &[u8; 32], internal_key: &[u8; 32]) -> (TxIn, TxOut) {
+    let control_block = [[0xc0].as_slice(), internal_key].concat();
+    let txin = TxIn {
+        previous_output: OutPoint::null(),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::from_slice(&[vec![0x30; 64], vec![0x51], control_block]),
+    };
+    let prevout = TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), output_key].concat()),
+    };
+    (txin, prevout)
+}
+
+#[test]
+fn test_p2tr_script_path_nums_internal_key_is_skipped() {
+    let secp = Secp256k1::new();
+    let (output_key, _) = SecretKey::from_slice(&[0x01; 32])
+        .unwrap()
+        .x_only_public_key(&secp);
+    let (txin, prevout) = p2tr_script_path_input(&output_key.serialize(), &crate::NUMS_H);
+
+    assert!(try_get_input_public_key(&txin, &prevout).is_err());
+}
+
+#[test]
+fn test_p2tr_script_path_uses_output_key() {
+    let secp = Secp256k1::new();
+    let (output_key, _) = SecretKey::from_slice(&[0x01; 32])
+        .unwrap()
+        .x_only_public_key(&secp);
+    let (internal_key, _) = SecretKey::from_slice(&[0x02; 32])
+        .unwrap()
+        .x_only_public_key(&secp);
+    let (txin, prevout) =
+        p2tr_script_path_input(&output_key.serialize(), &internal_key.serialize());
+
+    assert_eq!(
+        try_get_input_public_key(&txin, &prevout).unwrap(),
+        PublicKey::from_x_only_public_key(output_key, Parity::Even)
+    );
+}
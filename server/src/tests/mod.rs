@@ -1 +1,3 @@
+pub mod bip352;
 pub mod fixtures;
+pub mod inputs;
@@ -0,0 +1,158 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use secp256k1::{PublicKey, SecretKey};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info};
+
+use crate::{
+    Error, Result,
+    scan::Scanner,
+    store::{
+        Store,
+        model::{BlockEvent, BlockOutputs, TransactionFilter, Wallet, WalletEvent},
+    },
+};
+
+// Blocks per store query when scanning the blocks of a wallet.
+const SCAN_PAGE_BLOCKS: i64 = 100;
+
+// Scans the stored blocks for the outputs of the registered wallets and stores them with the
+// wallet's progress, so every block is scanned once per wallet. Outputs of disconnected blocks are
+// dropped by the store.
+pub struct WalletScanner {
+    store: Store,
+}
+
+// Wallets to scan after an event.
+enum Scan {
+    All,
+    Wallet(String),
+}
+
+impl WalletScanner {
+    pub fn new(store: Store) -> Self {
+        Self { store }
+    }
+
+    // Scan all wallets up to the tip, then again when blocks are connected, and newly registered
+    // wallets as they are registered.
+    pub async fn run(self) {
+        let mut blocks = self.store.subscribe_blocks();
+        let mut wallets = self.store.subscribe_wallets();
+        let mut scan = Scan::All;
+        loop {
+            match scan {
+                Scan::All => {
+                    // Events queued so far are covered by this scan.
+                    blocks = blocks.resubscribe();
+                    wallets = wallets.resubscribe();
+                    if let Err(err) = self.scan_wallets().await {
+                        error!("Failed to scan wallets: {}", err);
+                    }
+                }
+                Scan::Wallet(id) => {
+                    if let Err(err) = self.scan_wallet(id.clone()).await {
+                        error!("Failed to scan wallet {}: {}", id, err);
+                    }
+                }
+            }
+
+            scan = loop {
+                tokio::select! {
+                    event = blocks.recv() => match event {
+                        Ok(BlockEvent::Connected(_)) | Err(RecvError::Lagged(_)) => break Scan::All,
+                        Ok(BlockEvent::Disconnected(_)) => {}
+                        Err(RecvError::Closed) => return,
+                    },
+                    event = wallets.recv() => match event {
+                        Ok(WalletEvent::Registered(id)) => break Scan::Wallet(id),
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(_)) => break Scan::All,
+                        Err(RecvError::Closed) => return,
+                    },
+                }
+            };
+        }
+    }
+
+    async fn scan_wallets(&self) -> Result<()> {
+        for id in self.store.get_wallet_ids().await? {
+            self.scan_wallet(id).await?;
+        }
+        Ok(())
+    }
+
+    // Scan the stored blocks above the wallet's last scanned block, from its birthday on, up to the
+    // tip. Stops if the wallet or the blocks changed in the meantime, the next scan continues from
+    // the stored progress.
+    async fn scan_wallet(&self, id: String) -> Result<()> {
+        let Some(wallet) = self.store.get_wallet(id).await? else {
+            return Ok(());
+        };
+        let Some(tip) = self.store.get_synced_blocks_height().await? else {
+            return Ok(());
+        };
+        let mut scanned_height = wallet.scanned_height;
+        let mut from = scanned_height
+            .map_or(wallet.birthday, |height| height + 1)
+            .max(wallet.birthday);
+        if from > tip {
+            return Ok(());
+        }
+        info!(
+            "Scanning blocks {} to {} of wallet {}.",
+            from, tip, wallet.id
+        );
+
+        let scanner = Arc::new(wallet_scanner(&wallet)?);
+        while from <= tip {
+            let to = tip.min(from + SCAN_PAGE_BLOCKS - 1);
+            let blocks = self
+                .store
+                .get_transactions_by_height_range(from, to, TransactionFilter::default())
+                .await?;
+            from = to + 1;
+            let Some((height, hash)) = blocks
+                .last()
+                .map(|block| (block.height, block.block_hash.clone()))
+            else {
+                continue;
+            };
+
+            // Scanning is CPU bound, blocks can have thousands of transactions.
+            let scanner = scanner.clone();
+            let outputs = tokio::task::spawn_blocking(move || {
+                blocks
+                    .into_iter()
+                    .map(|block| {
+                        let outputs = scanner.scan_transactions(&block.transactions)?;
+                        Ok(BlockOutputs {
+                            height: block.height,
+                            block_hash: block.block_hash,
+                            outputs,
+                        })
+                    })
+                    .filter(|block| !matches!(block, Ok(block) if block.outputs.is_empty()))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .await??;
+            let stored = self
+                .store
+                .add_wallet_outputs(&wallet.id, scanned_height, height, &hash, outputs)
+                .await?;
+            if !stored {
+                return Ok(());
+            }
+            scanned_height = Some(height);
+        }
+        Ok(())
+    }
+}
+
+fn wallet_scanner(wallet: &Wallet) -> Result<Scanner> {
+    // Keys were validated when the wallet was registered.
+    let scan_key = SecretKey::from_str(&wallet.scan_key).map_err(|_| Error::InvalidInput)?;
+    let spend_key = PublicKey::from_str(&wallet.spend_key).map_err(|_| Error::InvalidInput)?;
+    Scanner::new(scan_key, spend_key, &wallet.labels)
+}